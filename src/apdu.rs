@@ -0,0 +1,305 @@
+//! Building commands and parsing answers without hand-rolled byte slicing.
+
+use std::ops::Deref;
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{
+    error::LedgerUtilityError,
+    path::{DerivationPath, PathEncoding},
+};
+
+/// The most data a short APDU can carry
+pub const MAX_DATA: usize = 255;
+/// The most data an extended length APDU can carry
+pub const MAX_EXTENDED_DATA: usize = 65535;
+/// Added to a BIP32 path index to derive it hardened, written `44'`
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// The hardened form of BIP32 path index `index`
+pub fn hardened(index: u32) -> u32 {
+    index | HARDENED
+}
+
+/// A command under construction, checked against the APDU size limits when built.
+///
+/// ```
+/// # use ledger_utility::apdu::{hardened, Apdu};
+/// let command = Apdu::new(0x55, 0x04)
+///     .p1(0x01)
+///     .bip32_path(&[hardened(44), hardened(118), hardened(0), 0, 0])?
+///     .build()?;
+/// # Ok::<(), ledger_utility::error::LedgerUtilityError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Apdu {
+    cla: u8,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: Vec<u8>,
+    extended: bool,
+}
+
+impl Apdu {
+    pub fn new(cla: u8, ins: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1: 0,
+            p2: 0,
+            data: Vec::new(),
+            extended: false,
+        }
+    }
+
+    pub fn p1(mut self, p1: u8) -> Self {
+        self.p1 = p1;
+        self
+    }
+
+    pub fn p2(mut self, p2: u8) -> Self {
+        self.p2 = p2;
+        self
+    }
+
+    /// Allow up to [MAX_EXTENDED_DATA] bytes of data, encoded with a three byte
+    /// length by [Apdu::serialize]. Ledger devices only accept short APDUs, so
+    /// this is for other transports; split long messages for a device with
+    /// [crate::Ledger::exchange_chunked] instead.
+    pub fn extended(mut self, extended: bool) -> Self {
+        self.extended = extended;
+        self
+    }
+
+    /// Append `data`
+    pub fn data(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.data.extend_from_slice(data.as_ref());
+        self
+    }
+
+    pub fn u8(self, value: u8) -> Self {
+        self.data([value])
+    }
+
+    /// Append `value` big-endian
+    pub fn u16(self, value: u16) -> Self {
+        self.data(value.to_be_bytes())
+    }
+
+    /// Append `value` big-endian
+    pub fn u32(self, value: u32) -> Self {
+        self.data(value.to_be_bytes())
+    }
+
+    /// Append a BIP32 path as most apps expect it: the number of indices
+    /// followed by each index as a big-endian `u32`
+    pub fn bip32_path(self, path: &[u32]) -> Result<Self, LedgerUtilityError> {
+        let depth = u8::try_from(path.len()).map_err(|_| {
+            LedgerUtilityError::InvalidApdu(format!("BIP32 path of {} indices", path.len()))
+        })?;
+        Ok(path
+            .iter()
+            .fold(self.u8(depth), |apdu, &index| apdu.u32(index)))
+    }
+
+    /// Append `path` laid out as the app expects
+    pub fn derivation_path(self, path: &DerivationPath, encoding: PathEncoding) -> Self {
+        self.data(path.encode(encoding))
+    }
+
+    fn check_length(&self) -> Result<(), LedgerUtilityError> {
+        let max = match self.extended {
+            true => MAX_EXTENDED_DATA,
+            false => MAX_DATA,
+        };
+        match self.data.len() <= max {
+            true => Ok(()),
+            false => Err(LedgerUtilityError::InvalidApdu(format!(
+                "{} bytes of data, at most {} fit",
+                self.data.len(),
+                max
+            ))),
+        }
+    }
+
+    /// The command to exchange with a [crate::Ledger]. [APDUCommand] only has
+    /// the short encoding, so this fails for more than [MAX_DATA] bytes even
+    /// if the APDU is extended.
+    pub fn build(self) -> Result<APDUCommand<Vec<u8>>, LedgerUtilityError> {
+        self.check_length()?;
+        if self.data.len() > MAX_DATA {
+            return Err(LedgerUtilityError::InvalidApdu(format!(
+                "{} bytes of data need the extended encoding, use Apdu::serialize",
+                self.data.len()
+            )));
+        }
+        Ok(APDUCommand {
+            cla: self.cla,
+            ins: self.ins,
+            p1: self.p1,
+            p2: self.p2,
+            data: self.data,
+        })
+    }
+
+    /// The raw command, with a three byte length if the APDU is extended
+    pub fn serialize(&self) -> Result<Vec<u8>, LedgerUtilityError> {
+        self.check_length()?;
+        let mut bytes = vec![self.cla, self.ins, self.p1, self.p2];
+        match self.extended {
+            true => {
+                bytes.push(0);
+                bytes.extend((self.data.len() as u16).to_be_bytes());
+            }
+            false => bytes.push(self.data.len() as u8),
+        }
+        bytes.extend(&self.data);
+        Ok(bytes)
+    }
+}
+
+impl TryFrom<Apdu> for APDUCommand<Vec<u8>> {
+    type Error = LedgerUtilityError;
+
+    fn try_from(apdu: Apdu) -> Result<Self, Self::Error> {
+        apdu.build()
+    }
+}
+
+/// Cursor over the fields of an answer, failing with
+/// [LedgerUtilityError::MalformedAnswer] instead of panicking when the data
+/// runs out.
+///
+/// Integers are read big-endian and variable length fields are prefixed with
+/// their length as a single byte, as is common among Ledger apps.
+#[derive(Debug, Clone)]
+pub struct ResponseReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ResponseReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// The bytes not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], LedgerUtilityError> {
+        if self.data.len() < len {
+            return Err(LedgerUtilityError::MalformedAnswer(format!(
+                "expected {} more byte(s), found {}",
+                len,
+                self.data.len()
+            )));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+
+    /// Read exactly `N` bytes
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], LedgerUtilityError> {
+        Ok(self
+            .take(N)?
+            .try_into()
+            .expect("take returns the requested length"))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, LedgerUtilityError> {
+        self.array().map(u8::from_be_bytes)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, LedgerUtilityError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, LedgerUtilityError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    /// Read a field prefixed with its length
+    pub fn field(&mut self) -> Result<&'a [u8], LedgerUtilityError> {
+        let len = self.u8()?;
+        self.take(len as usize)
+    }
+
+    /// Read a UTF-8 string prefixed with its length
+    pub fn string(&mut self) -> Result<String, LedgerUtilityError> {
+        String::from_utf8(self.field()?.to_vec())
+            .map_err(|e| LedgerUtilityError::MalformedAnswer(e.to_string()))
+    }
+
+    /// Read everything left
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    /// Check that the whole answer was read
+    pub fn finish(self) -> Result<(), LedgerUtilityError> {
+        match self.data.len() {
+            0 => Ok(()),
+            len => Err(LedgerUtilityError::MalformedAnswer(format!(
+                "{} unexpected trailing byte(s)",
+                len
+            ))),
+        }
+    }
+}
+
+impl<'a, B: Deref<Target = [u8]>> From<&'a APDUAnswer<B>> for ResponseReader<'a> {
+    fn from(answer: &'a APDUAnswer<B>) -> Self {
+        Self::new(answer.data())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apdu_builder() {
+        let command = Apdu::new(0x55, 0x04)
+            .p1(1)
+            .bip32_path(&[hardened(44), 1])
+            .unwrap()
+            .u16(0x0203)
+            .build()
+            .unwrap();
+        assert_eq!(command.p1, 1);
+        assert_eq!(command.data, [2, 0x80, 0, 0, 44, 0, 0, 0, 1, 2, 3].to_vec());
+
+        assert!(Apdu::new(0xe0, 0x02).data([0; 256]).build().is_err());
+        assert!(Apdu::new(0xe0, 0x02).bip32_path(&[0; 256]).is_err());
+
+        let extended = Apdu::new(0xe0, 0x02).extended(true).data([7; 256]);
+        let bytes = extended.serialize().unwrap();
+        assert_eq!(bytes[..7], [0xe0, 0x02, 0, 0, 0, 1, 0]);
+        assert_eq!(bytes.len(), 7 + 256);
+        assert!(extended.build().is_err());
+    }
+
+    #[test]
+    fn test_response_reader() {
+        let answer =
+            APDUAnswer::from_answer(vec![1, 0, 2, 3, b'a', b'b', b'c', 9, 0x90, 0x00]).unwrap();
+        let mut reader = ResponseReader::from(&answer);
+        assert_eq!(reader.u8().unwrap(), 1);
+        assert_eq!(reader.u16().unwrap(), 2);
+        assert_eq!(reader.string().unwrap(), "abc");
+        assert!(reader.clone().finish().is_err());
+        assert!(matches!(
+            reader.u16(),
+            Err(LedgerUtilityError::MalformedAnswer(_))
+        ));
+        assert_eq!(reader.rest(), [9]);
+        reader.finish().unwrap();
+    }
+}
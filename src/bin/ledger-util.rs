@@ -0,0 +1,11 @@
+use clap::Parser;
+use ledger_utility::cli::Cli;
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = cli.run(&mut std::io::stdout()).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,165 @@
+//! Synchronous wrappers around [crate::Connection] and [crate::Ledger] for
+//! code that does not run an async runtime.
+//!
+//! Each [Connection] owns a small tokio runtime that drives the async API and
+//! is shared with the ledgers it opens. These types must not be used from
+//! within an async context, where blocking on the runtime panics.
+
+use std::{ops::Deref, sync::Arc};
+
+use ledger_transport::{APDUAnswer, APDUCommand, Exchange};
+use tokio::runtime::Runtime;
+
+use crate::{
+    error::LedgerUtilityError,
+    info::{DeviceId, DeviceInfo},
+    retry::RetryPolicy,
+    ConnectionBuilder, Device,
+};
+
+fn runtime() -> Result<Arc<Runtime>, LedgerUtilityError> {
+    // one worker keeps the bluetooth event loop running between calls
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?;
+    Ok(Arc::new(runtime))
+}
+
+/// Blocking counterpart of [crate::Connection]
+pub struct Connection {
+    runtime: Arc<Runtime>,
+    inner: crate::Connection,
+}
+
+impl Connection {
+    /// Initialize every compiled-in transport, see [ConnectionBuilder::build]
+    pub fn new() -> Result<Self, LedgerUtilityError> {
+        Self::with_builder(ConnectionBuilder::new())
+    }
+
+    /// Initialize the transports selected by `builder`
+    pub fn with_builder(builder: ConnectionBuilder) -> Result<Self, LedgerUtilityError> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(builder.build())?;
+        Ok(Self { runtime, inner })
+    }
+
+    /// The wrapped async connection
+    pub fn inner(&self) -> &crate::Connection {
+        &self.inner
+    }
+
+    pub fn get_all_ledgers(&self) -> Result<Vec<Device>, LedgerUtilityError> {
+        self.runtime.block_on(self.inner.get_all_ledgers())
+    }
+
+    /// See [Device::info]
+    pub fn device_info(&self, device: &Device) -> Result<DeviceInfo, LedgerUtilityError> {
+        self.runtime.block_on(device.info())
+    }
+
+    pub fn connect(&self, device: Device) -> Result<Ledger, LedgerUtilityError> {
+        let ledger = self.runtime.block_on(self.inner.connect(device))?;
+        Ok(self.wrap(ledger))
+    }
+
+    /// See [crate::Connection::connect_with_id]
+    pub fn connect_with_id(
+        &self,
+        device_id: &DeviceId,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Ledger, LedgerUtilityError> {
+        let ledger = self
+            .runtime
+            .block_on(self.inner.connect_with_id(device_id, retry_policy))?;
+        Ok(self.wrap(ledger))
+    }
+
+    fn wrap(&self, inner: crate::Ledger) -> Ledger {
+        Ledger {
+            runtime: self.runtime.clone(),
+            inner,
+        }
+    }
+}
+
+/// Blocking counterpart of [crate::Ledger]
+pub struct Ledger {
+    runtime: Arc<Runtime>,
+    inner: crate::Ledger,
+}
+
+impl Ledger {
+    /// Wrap a ledger that was not opened through a blocking [Connection], such as a mock
+    pub fn new(inner: crate::Ledger) -> Result<Self, LedgerUtilityError> {
+        Ok(Self {
+            runtime: runtime()?,
+            inner,
+        })
+    }
+
+    /// The wrapped async ledger
+    pub fn inner(&self) -> &crate::Ledger {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> crate::Ledger {
+        self.inner
+    }
+
+    pub fn info(&self) -> Option<&DeviceInfo> {
+        self.inner.info()
+    }
+
+    pub fn exchange<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.inner.exchange(command))
+    }
+
+    /// See [crate::Ledger::exchange_checked]
+    pub fn exchange_checked<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.runtime.block_on(self.inner.exchange_checked(command))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(any(feature = "bluetooth", feature = "usb"))]
+    #[test]
+    #[serial_test::serial]
+    fn test_get_all_ledgers() {
+        let connection = Connection::new().unwrap();
+        for device in connection.get_all_ledgers().unwrap() {
+            connection.device_info(&device).unwrap();
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_exchange() {
+        use crate::{
+            mock::{Expectation, MockTransport},
+            test_util::command,
+        };
+
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().ins(0x01).respond(vec![7], 0x9000));
+        let ledger = Ledger::new(crate::Ledger::Mock(mock.clone())).unwrap();
+        assert_eq!(ledger.exchange(&command(0x01)).unwrap().data(), &[7]);
+        mock.verify().unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::ops::Deref;
+
+use btleplug::{api::Peripheral, platform};
+use ledger_bluetooth::TransportNativeBle;
+use ledger_transport::{APDUAnswer, APDUCommand, Exchange};
+
+use crate::{
+    error::LedgerUtilityError,
+    info::{DeviceId, DeviceInfo},
+    model::LedgerModel,
+    session::SessionLock,
+};
+
+/// A device connected over bluetooth
+pub struct BluetoothTransport {
+    transport: TransportNativeBle,
+    info: DeviceInfo,
+    pub(crate) session: SessionLock,
+}
+
+impl BluetoothTransport {
+    /// Wrap a transport the caller connected, for the device described by `info`
+    pub fn new(transport: TransportNativeBle, info: DeviceInfo) -> Self {
+        Self {
+            transport,
+            info,
+            session: SessionLock::default(),
+        }
+    }
+
+    pub(crate) async fn connect(
+        peripheral: platform::Peripheral,
+    ) -> Result<Self, LedgerUtilityError> {
+        // the metadata is optional, so a device that does not report its
+        // properties can still be opened
+        let info = describe(&peripheral)
+            .await
+            .unwrap_or_else(|_| DeviceInfo::new(id(&peripheral)));
+        let transport = TransportNativeBle::connect(peripheral).await?;
+        Ok(Self::new(transport, info))
+    }
+
+    /// Metadata of the device this transport was opened from
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    pub async fn exchange<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        Ok(self.transport.exchange(command).await?)
+    }
+}
+
+pub(crate) async fn describe(
+    peripheral: &platform::Peripheral,
+) -> Result<DeviceInfo, LedgerUtilityError> {
+    let properties = peripheral
+        .properties()
+        .await?
+        .ok_or(LedgerUtilityError::PropertiesUnavailable)?;
+    let mut info = DeviceInfo::new(id(peripheral));
+    info.model = properties
+        .services
+        .iter()
+        .find_map(LedgerModel::from_ble_service)
+        .or_else(|| {
+            properties
+                .local_name
+                .as_deref()
+                .and_then(LedgerModel::from_name)
+        });
+    info.product = properties.local_name;
+    info.ble_address = Some(peripheral.address().to_string());
+    info.rssi = properties.rssi;
+    Ok(info)
+}
+
+pub(crate) fn id(peripheral: &platform::Peripheral) -> DeviceId {
+    DeviceId::Bluetooth(peripheral.address().to_string())
+}
@@ -0,0 +1,69 @@
+/// The P1 values marking the position of each APDU of a chunked message.
+///
+/// Apps disagree on these: some mark only the first chunk, some mark the last
+/// one, and some use a dedicated value for a message that fits in one APDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkMarkers {
+    pub first: u8,
+    pub next: u8,
+    pub last: u8,
+    /// Used when the whole message fits in a single APDU
+    pub single: u8,
+}
+
+impl ChunkMarkers {
+    /// Markers for a message of several chunks, a single chunk is marked as `first`
+    pub fn new(first: u8, next: u8, last: u8) -> Self {
+        Self {
+            first,
+            next,
+            last,
+            single: first,
+        }
+    }
+
+    pub fn single(mut self, single: u8) -> Self {
+        self.single = single;
+        self
+    }
+
+    /// The P1 of chunk `index` out of `count`
+    pub fn p1(&self, index: usize, count: usize) -> u8 {
+        match index {
+            _ if count == 1 => self.single,
+            0 => self.first,
+            _ if index == count - 1 => self.last,
+            _ => self.next,
+        }
+    }
+}
+
+/// Split `payload` into chunks of at most `size` bytes. An empty payload is a single empty chunk.
+pub(crate) fn split(payload: &[u8], size: usize) -> Vec<&[u8]> {
+    match payload.is_empty() {
+        true => vec![payload],
+        false => payload.chunks(size).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_markers() {
+        let markers = ChunkMarkers::new(0, 1, 2);
+        assert_eq!(markers.p1(0, 1), 0);
+        let positions: Vec<u8> = (0..4).map(|i| markers.p1(i, 4)).collect();
+        assert_eq!(positions, vec![0, 1, 1, 2]);
+        assert_eq!(markers.single(2).p1(0, 1), 2);
+    }
+
+    #[test]
+    fn test_split() {
+        let payload = vec![0u8; 600];
+        let sizes: Vec<usize> = split(&payload, 255).iter().map(|x| x.len()).collect();
+        assert_eq!(sizes, vec![255, 255, 90]);
+        assert_eq!(split(&[], 255), vec![&[] as &[u8]]);
+    }
+}
@@ -0,0 +1,355 @@
+//! The `ledger-util` command line tool.
+//!
+//! Every subcommand is a plain function writing to `out`, so the tool can be
+//! tested against a mock or emulator without spawning the binary.
+
+#[cfg(feature = "tcp")]
+use std::net::SocketAddr;
+use std::{io::Write, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use ledger_transport::{APDUCommand, Exchange};
+use serde_json::json;
+
+use crate::{
+    error::LedgerUtilityError,
+    info::DeviceId,
+    retry::RetryPolicy,
+    script::{run_script, Script},
+    status::ApduStatus,
+    Connection, Ledger,
+};
+
+/// Inspect attached Ledger devices and send them APDUs
+#[derive(Debug, Parser)]
+#[command(name = "ledger-util", version)]
+pub struct Cli {
+    /// The device to use, as printed by `list`. Defaults to the first device found.
+    #[arg(long, global = true)]
+    pub device: Option<DeviceId>,
+    /// An emulator endpoint to list next to physical devices, may be repeated
+    #[cfg(feature = "tcp")]
+    #[arg(long = "tcp", global = true)]
+    pub tcp: Vec<SocketAddr>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the attached devices
+    List {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the device metadata, running app and firmware versions
+    Info,
+    /// List the apps installed on the device
+    Apps,
+    /// Open an app from the dashboard
+    Open { app: String },
+    /// Send a raw APDU given in hex, e.g. `e001000000`
+    Apdu { hex: String },
+    /// Run an APDU script and report every step, see the `script` module for the format
+    Script { file: PathBuf },
+    /// Share the attached devices with other processes over a Unix socket
+    #[cfg(all(feature = "daemon", unix))]
+    Daemon { socket: PathBuf },
+}
+
+impl Cli {
+    pub async fn connection(&self) -> Result<Connection, LedgerUtilityError> {
+        #[cfg_attr(not(feature = "tcp"), allow(unused_mut))]
+        let mut builder = Connection::builder();
+        #[cfg(feature = "tcp")]
+        for address in &self.tcp {
+            builder = builder.tcp_endpoint(*address);
+        }
+        builder.build().await
+    }
+
+    pub async fn run(&self, out: &mut dyn Write) -> Result<(), LedgerUtilityError> {
+        let connection = self.connection().await?;
+        if let Command::List { json } = self.command {
+            return list(&connection, json, out).await;
+        }
+        #[cfg(all(feature = "daemon", unix))]
+        if let Command::Daemon { socket } = &self.command {
+            return crate::daemon::Daemon::new(connection)
+                .serve_at(socket)
+                .await;
+        }
+
+        let ledger = open(&connection, self.device.as_ref()).await?;
+        match &self.command {
+            Command::List { .. } => unreachable!(),
+            #[cfg(all(feature = "daemon", unix))]
+            Command::Daemon { .. } => unreachable!(),
+            Command::Info => info(&ledger, out).await,
+            Command::Apps => apps(&ledger, out).await,
+            Command::Open { app } => ledger.dashboard().open_app(app).await,
+            Command::Apdu { hex } => apdu(&ledger, hex, out).await,
+            Command::Script { file } => script(&ledger, &Script::open(file)?, out).await,
+        }
+    }
+}
+
+/// Connect to `device`, or to the first device found
+pub async fn open(
+    connection: &Connection,
+    device: Option<&DeviceId>,
+) -> Result<Ledger, LedgerUtilityError> {
+    match device {
+        Some(id) => connection.connect_with_id(id, RetryPolicy::once()).await,
+        None => {
+            let device = connection
+                .get_all_ledgers()
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(LedgerUtilityError::DeviceNotFound)?;
+            connection.connect(device).await
+        }
+    }
+}
+
+pub async fn list(
+    connection: &Connection,
+    json: bool,
+    out: &mut dyn Write,
+) -> Result<(), LedgerUtilityError> {
+    let mut rows = Vec::new();
+    for device in connection.get_all_ledgers().await? {
+        rows.push((device.id(), device.info().await.ok()));
+    }
+
+    if json {
+        let devices: Vec<_> = rows
+            .iter()
+            .map(|(id, info)| {
+                let info = info.as_ref();
+                json!({
+                    "id": id.to_string(),
+                    "transport": id.transport().to_string(),
+                    "model": info.and_then(|x| x.model).map(|x| x.to_string()),
+                    "product": info.and_then(|x| x.product.clone()),
+                    "vendor_id": info.and_then(|x| x.vendor_id),
+                    "product_id": info.and_then(|x| x.product_id),
+                    "serial_number": info.and_then(|x| x.serial_number.clone()),
+                    "rssi": info.and_then(|x| x.rssi),
+                })
+            })
+            .collect();
+        writeln!(out, "{}", serde_json::Value::from(devices))?;
+        return Ok(());
+    }
+
+    let cells: Vec<[String; 3]> = rows
+        .iter()
+        .map(|(id, info)| {
+            let info = info.as_ref();
+            [
+                id.to_string(),
+                info.and_then(|x| x.model)
+                    .map_or(String::from("-"), |x| x.to_string()),
+                info.and_then(|x| x.product.clone())
+                    .unwrap_or(String::from("-")),
+            ]
+        })
+        .collect();
+    let width = |column: usize| {
+        cells
+            .iter()
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or(0)
+            .max(2)
+    };
+    let (id_width, model_width) = (width(0), width(1).max(5));
+    writeln!(out, "{:id_width$}  {:model_width$}  PRODUCT", "ID", "MODEL")?;
+    for [id, model, product] in cells {
+        writeln!(out, "{:id_width$}  {:model_width$}  {}", id, model, product)?;
+    }
+    Ok(())
+}
+
+pub async fn info(ledger: &Ledger, out: &mut dyn Write) -> Result<(), LedgerUtilityError> {
+    if let Some(info) = ledger.info() {
+        writeln!(out, "id: {}", info.id)?;
+        if let Some(model) = info.model {
+            writeln!(out, "model: {}", model)?;
+        }
+        if let Some(product) = &info.product {
+            writeln!(out, "product: {}", product)?;
+        }
+    }
+    let app = ledger.dashboard().app_and_version().await?;
+    writeln!(out, "app: {} {}", app.name, app.version)?;
+    // the firmware versions are only answered by the dashboard
+    if app.is_dashboard() {
+        let firmware = ledger.dashboard().device_info().await?;
+        writeln!(out, "target id: {:08x}", firmware.target_id)?;
+        writeln!(out, "se version: {}", firmware.se_version)?;
+        writeln!(out, "mcu version: {}", firmware.mcu_version)?;
+    }
+    Ok(())
+}
+
+pub async fn apps(ledger: &Ledger, out: &mut dyn Write) -> Result<(), LedgerUtilityError> {
+    for app in ledger.dashboard().list_apps().await? {
+        writeln!(out, "{}  {}", hex::encode(&app.hash), app.name)?;
+    }
+    Ok(())
+}
+
+pub async fn apdu(
+    ledger: &Ledger,
+    apdu: &str,
+    out: &mut dyn Write,
+) -> Result<(), LedgerUtilityError> {
+    let command = parse_apdu(apdu)?;
+    let answer = ledger.exchange(&command).await?;
+    if !answer.data().is_empty() {
+        writeln!(out, "{}", hex::encode(answer.data()))?;
+    }
+    writeln!(out, "{}", ApduStatus::from(answer.retcode()))?;
+    Ok(())
+}
+
+/// Run `script`, printing a line per step, and fail if any step failed
+pub async fn script(
+    ledger: &Ledger,
+    script: &Script,
+    out: &mut dyn Write,
+) -> Result<(), LedgerUtilityError> {
+    let report = run_script(ledger, script).await?;
+    for step in &report.steps {
+        writeln!(
+            out,
+            "{:4}  line {:<4} {:>5}ms  {}  {:04x}{}",
+            if step.passed() { "ok" } else { "FAIL" },
+            step.line,
+            step.duration.as_millis(),
+            hex::encode(&step.command),
+            step.status,
+            step.failure
+                .as_ref()
+                .map(|reason| format!("  {}", reason))
+                .unwrap_or_default()
+        )?;
+    }
+    report.result()
+}
+
+/// Parse a hex encoded short APDU: CLA, INS, P1, P2 and optionally Lc followed by the data
+pub fn parse_apdu(apdu: &str) -> Result<APDUCommand<Vec<u8>>, LedgerUtilityError> {
+    let bytes = hex::decode(apdu.trim())
+        .map_err(|e| LedgerUtilityError::InvalidApdu(format!("invalid hex: {}", e)))?;
+    let (header, data) = match bytes.len() {
+        0..=3 => {
+            return Err(LedgerUtilityError::InvalidApdu(String::from(
+                "expected at least CLA, INS, P1 and P2",
+            )))
+        }
+        4 => (&bytes[..], &[][..]),
+        _ => (&bytes[..4], &bytes[5..]),
+    };
+    if bytes.len() > 4 && bytes[4] as usize != data.len() {
+        return Err(LedgerUtilityError::InvalidApdu(format!(
+            "Lc is {} but {} data byte(s) follow",
+            bytes[4],
+            data.len()
+        )));
+    }
+    Ok(APDUCommand {
+        cla: header[0],
+        ins: header[1],
+        p1: header[2],
+        p2: header[3],
+        data: data.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_apdu() {
+        let command = parse_apdu("e0020000 02 aabb".replace(' ', "").as_str()).unwrap();
+        assert_eq!(command.serialize(), vec![0xe0, 0x02, 0, 0, 2, 0xaa, 0xbb]);
+        assert_eq!(parse_apdu("b0010000").unwrap().data, Vec::<u8>::new());
+        assert!(matches!(
+            parse_apdu("e0020000 03 aabb".replace(' ', "").as_str()),
+            Err(LedgerUtilityError::InvalidApdu(_))
+        ));
+        assert!(parse_apdu("e002").is_err());
+    }
+
+    #[cfg(feature = "tcp")]
+    #[tokio::test]
+    async fn test_list() {
+        let cli = Cli::parse_from(["ledger-util", "--tcp", "127.0.0.1:9999", "list", "--json"]);
+        let connection = cli.connection().await.unwrap();
+        let mut out = Vec::new();
+        list(&connection, true, &mut out).await.unwrap();
+        let devices: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert!(devices
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|x| x["id"] == "tcp:127.0.0.1:9999"));
+
+        let mut out = Vec::new();
+        list(&connection, false, &mut out).await.unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.starts_with("ID"));
+        assert!(table.contains("tcp:127.0.0.1:9999"));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_info_and_script() {
+        use crate::mock::{Expectation, MockTransport};
+
+        let mock = MockTransport::new();
+        let mut app = vec![1, 5];
+        app.extend_from_slice(b"BOLOS");
+        app.extend_from_slice(&[5]);
+        app.extend_from_slice(b"1.1.1");
+        let mut firmware = vec![0x33, 0x10, 0x00, 0x04, 5];
+        firmware.extend_from_slice(b"2.1.0");
+        firmware.extend_from_slice(&[0, 4]);
+        firmware.extend_from_slice(b"5.24");
+        mock.expect(Expectation::new().ins(0x01).respond(app, 0x9000))
+            .expect(Expectation::new().ins(0x01).respond(firmware, 0x9000))
+            .expect(Expectation::new().ins(0x02).respond(vec![0xaa], 0x9000))
+            .expect(Expectation::new().ins(0x03).respond(vec![], 0x6985));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let mut out = Vec::new();
+        info(&ledger, &mut out).await.unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("app: BOLOS 1.1.1"));
+        assert!(text.contains("se version: 2.1.0"));
+
+        let script = "send e0 02 00 00\nexpect 0 aa\nsend e0 03 00 00\n"
+            .parse()
+            .unwrap();
+        let mut out = Vec::new();
+        let error = super::script(&ledger, &script, &mut out).await.unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::ScriptFailed { line: 3, .. }
+        ));
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("ok    line 1"));
+        assert!(lines[1].starts_with("FAIL  line 3"));
+        assert!(lines[1].ends_with(
+            "e003000000  6985  expected status success (0x9000), got rejected by the user (0x6985)"
+        ));
+        mock.verify().unwrap();
+    }
+}
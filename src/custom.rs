@@ -0,0 +1,184 @@
+use std::{fmt::Debug, ops::Deref, sync::Arc};
+
+use ledger_transport::{async_trait, APDUAnswer, APDUCommand};
+
+use crate::{error::LedgerUtilityError, info::DeviceInfo, session::SessionLock};
+
+/// A transport implemented outside this crate, such as a relay to devices
+/// attached to another machine.
+///
+/// Backends are registered with [crate::ConnectionBuilder::backend] or
+/// [crate::Connection::add_backend]. Their devices are listed by
+/// [crate::Connection::get_all_ledgers] as [crate::Device::Custom] next to the
+/// built-in ones and open as [crate::Ledger::Custom].
+///
+/// Implement it with the `async_trait` attribute re-exported by `ledger_transport`.
+#[async_trait]
+pub trait LedgerTransport: Send + Sync {
+    /// Short name of the backend, used in [crate::info::DeviceId::Custom] and device names
+    fn name(&self) -> &str;
+
+    /// List the devices currently reachable through this backend. Their ids
+    /// should be [crate::info::DeviceId::Custom] with this backend's name, so
+    /// they can be told apart from the devices of other backends.
+    async fn discover(&self) -> Result<Vec<DeviceInfo>, LedgerUtilityError>;
+
+    /// Open a device previously returned by [LedgerTransport::discover]
+    async fn connect(&self, device: &DeviceInfo)
+        -> Result<Box<dyn LedgerLink>, LedgerUtilityError>;
+}
+
+impl Debug for dyn LedgerTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LedgerTransport")
+            .field(&self.name())
+            .finish()
+    }
+}
+
+/// An open connection to a device of a [LedgerTransport].
+///
+/// Exchanges are serialized by the [crate::Ledger] owning the link, so
+/// implementations need not guard against concurrent commands. Failures of the
+/// link itself are best reported as [LedgerUtilityError::Custom], so that
+/// [LedgerUtilityError::is_transport] recognizes them.
+#[async_trait]
+pub trait LedgerLink: Send + Sync {
+    async fn exchange(
+        &self,
+        command: &APDUCommand<&[u8]>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>;
+}
+
+/// A device found by a registered [LedgerTransport]
+#[derive(Debug, Clone)]
+pub struct CustomDevice {
+    pub(crate) backend: Arc<dyn LedgerTransport>,
+    pub(crate) info: DeviceInfo,
+}
+
+impl CustomDevice {
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// The name of the backend that found this device
+    pub fn backend(&self) -> &str {
+        self.backend.name()
+    }
+}
+
+/// A device opened through a [LedgerTransport]
+pub struct CustomTransport {
+    link: Box<dyn LedgerLink>,
+    info: DeviceInfo,
+    pub(crate) session: SessionLock,
+}
+
+impl CustomTransport {
+    /// Wrap a link opened outside of a [crate::Connection]
+    pub fn new(info: DeviceInfo, link: Box<dyn LedgerLink>) -> Self {
+        Self {
+            link,
+            info,
+            session: SessionLock::default(),
+        }
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    pub async fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let command = APDUCommand {
+            cla: command.cla,
+            ins: command.ins,
+            p1: command.p1,
+            p2: command.p2,
+            data: &command.data[..],
+        };
+        self.link.exchange(&command).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ledger_transport::Exchange;
+
+    use super::*;
+    use crate::{
+        info::DeviceId, retry::RetryPolicy, test_util::command_with, Connection, Ledger, Transport,
+    };
+
+    /// A backend with a single device that echoes every command back
+    struct Echo;
+
+    struct EchoLink;
+
+    #[async_trait]
+    impl LedgerTransport for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        async fn discover(&self) -> Result<Vec<DeviceInfo>, LedgerUtilityError> {
+            let mut info = DeviceInfo::new(DeviceId::Custom {
+                backend: String::from("echo"),
+                id: String::from("0"),
+            });
+            info.product = Some(String::from("Echo"));
+            Ok(vec![info])
+        }
+
+        async fn connect(
+            &self,
+            _device: &DeviceInfo,
+        ) -> Result<Box<dyn LedgerLink>, LedgerUtilityError> {
+            Ok(Box::new(EchoLink))
+        }
+    }
+
+    #[async_trait]
+    impl LedgerLink for EchoLink {
+        async fn exchange(
+            &self,
+            command: &APDUCommand<&[u8]>,
+        ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+            let mut answer = command.data.to_vec();
+            answer.extend([0x90, 0x00]);
+            Ok(APDUAnswer::from_answer(answer).expect("answer has a status word"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_backend() {
+        let builder = Connection::builder().backend(Echo);
+        #[cfg(feature = "bluetooth")]
+        let builder = builder.bluetooth(false);
+        #[cfg(feature = "usb")]
+        let builder = builder.usb(false);
+        let connection = builder.build().await.unwrap();
+
+        let devices = connection.get_all_ledgers().await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name().await.unwrap(), "echo: Echo");
+        let info = devices[0].info().await.unwrap();
+        assert_eq!(info.transport, Transport::Custom);
+
+        let id = "custom:echo:0".parse::<DeviceId>().unwrap();
+        let ledger = connection
+            .connect_with_id(&id, RetryPolicy::once())
+            .await
+            .unwrap();
+        assert!(matches!(ledger, Ledger::Custom(_)));
+        assert_eq!(ledger.id(), Some(&id));
+        let answer = ledger
+            .exchange(&command_with(0x01, vec![1, 2, 3]))
+            .await
+            .unwrap();
+        assert_eq!(answer.data(), &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,643 @@
+//! A local daemon that owns the [Connection] and shares its ledgers with other
+//! processes over a Unix domain socket, and the matching [RemoteTransport].
+//!
+//! Every message is a big-endian `u32` length followed by a JSON document.
+//! Clients send a [Request] and receive exactly one [Response] for it.
+
+use std::{collections::HashMap, ops::Deref, path::Path, sync::Arc, time::Duration};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{oneshot, Mutex},
+};
+
+use crate::{
+    error::LedgerUtilityError,
+    info::{DeviceId, DeviceInfo},
+    retry::RetryPolicy,
+    session::{SessionGuard, SessionLock},
+    Connection, Ledger,
+};
+
+/// Messages larger than this are rejected rather than allocated
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// An APDU as carried over the socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteCommand {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// Enumerate the devices attached to the daemon's host
+    List,
+    /// Open the device, or share it if it is already open
+    Connect { id: DeviceId },
+    Exchange {
+        id: DeviceId,
+        command: RemoteCommand,
+    },
+    /// Hold the device for this client, like a [crate::Session] does within a
+    /// process, so the exchanges of other clients wait until it is unlocked or
+    /// the client disconnects. A client may lock a device it holds again, it
+    /// is then released by as many unlocks.
+    Lock {
+        id: DeviceId,
+        /// Give up if the device is not available within this many milliseconds
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Release a [Request::Lock]
+    Unlock { id: DeviceId },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Devices {
+        devices: Vec<DeviceInfo>,
+    },
+    Connected {
+        info: DeviceInfo,
+    },
+    /// The answer data followed by the status word
+    Answer {
+        answer: Vec<u8>,
+    },
+    Locked,
+    Unlocked,
+    Error {
+        message: String,
+    },
+}
+
+async fn read_message<T: DeserializeOwned>(
+    stream: &mut UnixStream,
+) -> Result<Option<T>, LedgerUtilityError> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        // the peer hung up between messages
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(LedgerUtilityError::Remote(format!(
+            "message of {} bytes is too large",
+            len
+        )));
+    }
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message).await?;
+    serde_json::from_slice(&message)
+        .map(Some)
+        .map_err(|e| LedgerUtilityError::Remote(format!("invalid message: {}", e)))
+}
+
+async fn write_message<T: Serialize>(
+    stream: &mut UnixStream,
+    message: &T,
+) -> Result<(), LedgerUtilityError> {
+    let message = serde_json::to_vec(message).expect("messages serialize to JSON");
+    stream
+        .write_all(&(message.len() as u32).to_be_bytes())
+        .await?;
+    stream.write_all(&message).await?;
+    Ok(())
+}
+
+/// A connection to a [Daemon], holding the devices it locked until it is dropped
+#[derive(Default)]
+pub struct Client {
+    /// The session of every locked device and how many times it was locked
+    sessions: HashMap<DeviceId, (SessionGuard, usize)>,
+}
+
+/// Serves the devices of a [Connection] to the clients of a Unix socket.
+///
+/// A device is opened by the first client that uses it and stays open, shared
+/// by every client, until the link to it fails. The next request reopens it.
+/// Every client is served on a task of its own; clones share the same devices.
+#[derive(Clone)]
+pub struct Daemon {
+    connection: Arc<Connection>,
+    ledgers: Arc<Mutex<HashMap<DeviceId, Arc<Ledger>>>>,
+}
+
+impl Daemon {
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection: Arc::new(connection),
+            ledgers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Share an already open ledger, such as an emulator or a replay, as `id`
+    pub async fn insert(&self, id: DeviceId, ledger: Ledger) {
+        self.ledgers.lock().await.insert(id, Arc::new(ledger));
+    }
+
+    /// Listen on a new socket at `path`, replacing a stale socket left by a previous daemon
+    pub async fn serve_at(&self, path: impl AsRef<Path>) -> Result<(), LedgerUtilityError> {
+        let path = path.as_ref();
+        if path.exists() && UnixStream::connect(path).await.is_err() {
+            std::fs::remove_file(path)?;
+        }
+        self.serve(UnixListener::bind(path)?).await
+    }
+
+    /// Accept clients until accepting fails, serving each on a new task
+    pub async fn serve(&self, listener: UnixListener) -> Result<(), LedgerUtilityError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let daemon = self.clone();
+            tokio::spawn(async move { daemon.serve_client(stream).await });
+        }
+    }
+
+    async fn serve_client(&self, mut stream: UnixStream) {
+        let mut client = Client::default();
+        while let Ok(Some(request)) = read_message(&mut stream).await {
+            let response =
+                self.handle(&mut client, request)
+                    .await
+                    .unwrap_or_else(|e| Response::Error {
+                        message: e.to_string(),
+                    });
+            if write_message(&mut stream, &response).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Answer a single request of `client`
+    pub async fn handle(
+        &self,
+        client: &mut Client,
+        request: Request,
+    ) -> Result<Response, LedgerUtilityError> {
+        match request {
+            Request::List => {
+                let mut devices = Vec::new();
+                for device in self.connection.get_all_ledgers().await? {
+                    if let Ok(info) = device.info().await {
+                        devices.push(info);
+                    }
+                }
+                Ok(Response::Devices { devices })
+            }
+            Request::Connect { id } => {
+                let ledger = self.ledger(&id).await?;
+                let info = ledger.info().cloned().unwrap_or(DeviceInfo::new(id));
+                Ok(Response::Connected { info })
+            }
+            Request::Exchange { id, command } => {
+                let ledger = self.ledger(&id).await?;
+                let command = APDUCommand {
+                    cla: command.cla,
+                    ins: command.ins,
+                    p1: command.p1,
+                    p2: command.p2,
+                    data: command.data,
+                };
+                // blocking transports exchange on a thread of their own rather
+                // than stalling the runtime every client is served on
+                let guard = match client.sessions.contains_key(&id) {
+                    true => None,
+                    false => Some(ledger.session_lock().lock_owned().await),
+                };
+                match ledger.exchange_interruptible(&command, guard).await {
+                    Ok(answer) => {
+                        let mut raw = answer.apdu_data().to_vec();
+                        raw.extend_from_slice(&answer.retcode().to_be_bytes());
+                        Ok(Response::Answer { answer: raw })
+                    }
+                    Err(e) => {
+                        if e.is_transport() {
+                            // the handle is likely dead, unless another client
+                            // already replaced it
+                            let mut ledgers = self.ledgers.lock().await;
+                            if ledgers
+                                .get(&id)
+                                .is_some_and(|open| Arc::ptr_eq(open, &ledger))
+                            {
+                                ledgers.remove(&id);
+                            }
+                        }
+                        Err(e)
+                    }
+                }
+            }
+            Request::Lock { id, timeout_ms } => {
+                if let Some((_, count)) = client.sessions.get_mut(&id) {
+                    *count += 1;
+                    return Ok(Response::Locked);
+                }
+                let ledger = self.ledger(&id).await?;
+                let lock = ledger.session_lock().lock_owned();
+                let guard = match timeout_ms.map(Duration::from_millis) {
+                    Some(timeout) => tokio::time::timeout(timeout, lock)
+                        .await
+                        .map_err(|_| LedgerUtilityError::SessionTimeout(timeout))?,
+                    None => lock.await,
+                };
+                client.sessions.insert(id, (guard, 1));
+                Ok(Response::Locked)
+            }
+            Request::Unlock { id } => match client.sessions.get_mut(&id) {
+                Some((_, count)) if *count > 1 => {
+                    *count -= 1;
+                    Ok(Response::Unlocked)
+                }
+                Some(_) => {
+                    client.sessions.remove(&id);
+                    Ok(Response::Unlocked)
+                }
+                None => Err(LedgerUtilityError::Remote(format!("{} is not locked", id))),
+            },
+        }
+    }
+
+    /// The ledger `id`, opened by the first client that uses it and shared with the others
+    async fn ledger(&self, id: &DeviceId) -> Result<Arc<Ledger>, LedgerUtilityError> {
+        if let Some(ledger) = self.ledgers.lock().await.get(id) {
+            return Ok(ledger.clone());
+        }
+        // connecting can take a while, the other clients keep using their ledgers
+        let ledger = Arc::new(
+            self.connection
+                .connect_with_id(id, RetryPolicy::once())
+                .await?,
+        );
+        // another client may have opened the device meanwhile, all share one handle
+        let mut ledgers = self.ledgers.lock().await;
+        Ok(ledgers.entry(id.clone()).or_insert(ledger).clone())
+    }
+}
+
+async fn request(
+    stream: &mut UnixStream,
+    request: &Request,
+) -> Result<Response, LedgerUtilityError> {
+    write_message(stream, request).await?;
+    match read_message(stream).await? {
+        Some(Response::Error { message }) => Err(LedgerUtilityError::Remote(message)),
+        Some(response) => Ok(response),
+        None => Err(LedgerUtilityError::Remote(String::from(
+            "daemon closed the connection",
+        ))),
+    }
+}
+
+fn unexpected(response: Response) -> LedgerUtilityError {
+    LedgerUtilityError::Remote(format!("unexpected response {:?}", response))
+}
+
+/// List the devices attached to the daemon listening at `path`
+pub async fn list_remote(path: impl AsRef<Path>) -> Result<Vec<DeviceInfo>, LedgerUtilityError> {
+    let mut stream = UnixStream::connect(path).await?;
+    match request(&mut stream, &Request::List).await? {
+        Response::Devices { devices } => Ok(devices),
+        response => Err(unexpected(response)),
+    }
+}
+
+/// A device opened through a [Daemon]
+pub struct RemoteTransport {
+    stream: Arc<Mutex<UnixStream>>,
+    info: DeviceInfo,
+    pub(crate) session: SessionLock,
+}
+
+impl RemoteTransport {
+    /// Open the device `id` through the daemon listening at `path`
+    pub async fn connect(
+        path: impl AsRef<Path>,
+        id: &DeviceId,
+    ) -> Result<Self, LedgerUtilityError> {
+        let mut stream = UnixStream::connect(path).await?;
+        let connect = Request::Connect { id: id.clone() };
+        match request(&mut stream, &connect).await? {
+            Response::Connected { info } => Ok(Self {
+                stream: Arc::new(Mutex::new(stream)),
+                info,
+                session: SessionLock::default(),
+            }),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Metadata of the device, as reported by the daemon
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// Hold the device on the daemon until the returned guard is dropped
+    pub(crate) async fn lock(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<RemoteSession<'_>, LedgerUtilityError> {
+        let lock = Request::Lock {
+            id: self.info.id.clone(),
+            timeout_ms: timeout.map(|timeout| timeout.as_millis() as u64),
+        };
+        match self.send(lock, None).await? {
+            Response::Locked => Ok(RemoteSession(self)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub async fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        self.exchange_detached(command, None).await
+    }
+
+    /// Exchange from a task of its own, so the caller can stop waiting for an
+    /// answer. `guard` is held until the daemon has answered.
+    pub(crate) async fn exchange_detached<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let exchange = Request::Exchange {
+            id: self.info.id.clone(),
+            command: RemoteCommand {
+                cla: command.cla,
+                ins: command.ins,
+                p1: command.p1,
+                p2: command.p2,
+                data: command.data.to_vec(),
+            },
+        };
+        match self.send(exchange, guard).await? {
+            Response::Answer { answer } => {
+                APDUAnswer::from_answer(answer).map_err(|_| LedgerUtilityError::AnswerTooShort)
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Send `message` from a task that owns the stream until the response is
+    /// read, so a caller that stops waiting neither cuts the message short nor
+    /// leaves the response to be taken as the answer to the next request
+    async fn send(
+        &self,
+        message: Request,
+        guard: Option<SessionGuard>,
+    ) -> Result<Response, LedgerUtilityError> {
+        let stream = self.stream.clone();
+        let id = self.info.id.clone();
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let mut stream = stream.lock().await;
+            let response = request(&mut stream, &message).await;
+            if let Err(Ok(Response::Locked)) = sender.send(response) {
+                // no session was returned to unlock the device when dropped
+                let _ = request(&mut stream, &Request::Unlock { id }).await;
+            }
+        });
+        receiver.await.unwrap_or_else(|_| {
+            Err(LedgerUtilityError::Remote(String::from(
+                "request to the daemon failed",
+            )))
+        })
+    }
+}
+
+/// The daemon's side of a [crate::Session] with a remote ledger
+pub(crate) struct RemoteSession<'a>(&'a RemoteTransport);
+
+impl Drop for RemoteSession<'_> {
+    fn drop(&mut self) {
+        // the unlock cannot be awaited here, so it is sent from a task of its
+        // own. Without a runtime the daemon releases the device only once the
+        // connection is closed.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let stream = self.0.stream.clone();
+        let unlock = Request::Unlock {
+            id: self.0.info.id.clone(),
+        };
+        runtime.spawn(async move {
+            let mut stream = stream.lock().await;
+            let _ = request(&mut stream, &unlock).await;
+        });
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use ledger_transport::Exchange;
+    use serial_test::serial;
+
+    use super::*;
+    use crate::{
+        mock::{Expectation, MockTransport},
+        test_util::{command, command_with, connection, with_daemon},
+    };
+
+    fn mock_id(id: &str) -> DeviceId {
+        DeviceId::Custom {
+            backend: String::from("mock"),
+            id: String::from(id),
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_remote_exchange() {
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().ins(0x01).respond(vec![1, 2], 0x9000))
+            .expect(Expectation::new().ins(0x02).respond(vec![], 0x6985));
+        let id = mock_id("1");
+        let daemon = Daemon::new(connection().await);
+        daemon.insert(id.clone(), Ledger::Mock(mock.clone())).await;
+
+        with_daemon(&daemon, |path| async move {
+            let first = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            let second = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            assert_eq!(first.id(), Some(&id));
+
+            let answer = first
+                .exchange(&command_with(0x01, vec![0xaa]))
+                .await
+                .unwrap();
+            assert_eq!(answer.data(), &[1, 2]);
+            let answer = second
+                .exchange(&command_with(0x02, vec![0xaa]))
+                .await
+                .unwrap();
+            assert_eq!(answer.retcode(), 0x6985);
+
+            let error = RemoteTransport::connect(&path, &mock_id("missing"))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(error, LedgerUtilityError::Remote(_)));
+        })
+        .await;
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_remote_session() {
+        let mock = MockTransport::new();
+        for ins in [1, 2, 3, 4] {
+            mock.expect(Expectation::new().ins(ins));
+        }
+        let id = mock_id("1");
+        let daemon = Daemon::new(connection().await);
+        daemon.insert(id.clone(), Ledger::Mock(mock.clone())).await;
+
+        with_daemon(&daemon, |path| async move {
+            let first = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            let second = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            let session = first.session().await.unwrap();
+            session.exchange(&command(1)).await.unwrap();
+            // the other client cannot get through while the first holds the
+            // device, its command waits on the daemon
+            let error = second
+                .exchange_with_timeout(&command(3), Duration::from_millis(20))
+                .await
+                .unwrap_err();
+            assert!(matches!(error, LedgerUtilityError::ExchangeTimeout(_)));
+            session.exchange(&command(2)).await.unwrap();
+            drop(session);
+            second.exchange(&command(4)).await.unwrap();
+        })
+        .await;
+        mock.verify().unwrap();
+    }
+
+    /// Only a failed link closes a device, which is reopened on the next request
+    #[tokio::test]
+    #[serial]
+    async fn test_eviction() {
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().fail(LedgerUtilityError::AnswerTooShort))
+            .expect(Expectation::new().ins(0x02))
+            .expect(Expectation::new().fail(std::io::Error::other("unplugged").into()));
+        let id = mock_id("1");
+        let daemon = Daemon::new(connection().await);
+        daemon.insert(id.clone(), Ledger::Mock(mock.clone())).await;
+
+        with_daemon(&daemon, |path| async move {
+            let ledger = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            ledger.exchange(&command(0x01)).await.unwrap_err();
+            ledger.exchange(&command(0x02)).await.unwrap();
+            ledger.exchange(&command(0x03)).await.unwrap_err();
+            // the mock is gone, and the connection has no such device to reopen
+            ledger.exchange(&command(0x04)).await.unwrap_err();
+        })
+        .await;
+        mock.verify().unwrap();
+    }
+
+    /// An abandoned exchange does not leave its answer to the next one
+    #[cfg(feature = "tcp")]
+    #[tokio::test]
+    #[serial]
+    async fn test_remote_timeout() {
+        use std::sync::mpsc;
+
+        use crate::{tcp::TcpTransport, test_util::emulator};
+
+        // answers with the instruction of each command, the first one only once released
+        let (release, released) = mpsc::channel();
+        let address = emulator(move |apdu| {
+            if apdu[1] == 0x01 {
+                released.recv().unwrap();
+            }
+            Some(vec![apdu[1], 0x90, 0x00])
+        });
+        let id = DeviceId::Tcp(address);
+        let daemon = Daemon::new(connection().await);
+        let emulator = TcpTransport::connect(address).unwrap();
+        daemon.insert(id.clone(), Ledger::Tcp(emulator)).await;
+
+        with_daemon(&daemon, |path| async move {
+            let ledger = Ledger::Remote(RemoteTransport::connect(&path, &id).await.unwrap());
+            let error = ledger
+                .exchange_with_timeout(&command(0x01), Duration::from_millis(20))
+                .await
+                .unwrap_err();
+            assert!(matches!(error, LedgerUtilityError::ExchangeTimeout(_)));
+            release.send(()).unwrap();
+            let answer = ledger.exchange(&command(0x02)).await.unwrap();
+            assert_eq!(answer.data(), &[0x02]);
+        })
+        .await;
+    }
+
+    /// A client stuck on a blocking transport does not hold up the others
+    #[cfg(feature = "tcp")]
+    #[tokio::test]
+    #[serial]
+    async fn test_blocking_client() {
+        use std::sync::mpsc;
+
+        use tokio::sync::oneshot;
+
+        use crate::{tcp::TcpTransport, test_util::emulator};
+
+        // an emulator that reports the command it read, and answers it once
+        // released or hangs up if that takes too long
+        let (received, on_received) = oneshot::channel();
+        let mut received = Some(received);
+        let (release, released) = mpsc::channel();
+        let address = emulator(move |_| {
+            if let Some(received) = received.take() {
+                let _ = received.send(());
+            }
+            released
+                .recv_timeout(Duration::from_secs(2))
+                .ok()
+                .map(|()| vec![0x90, 0x00])
+        });
+
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().ins(0x02));
+        let slow = DeviceId::Tcp(address);
+        let fast = mock_id("1");
+        let daemon = Daemon::new(connection().await);
+        let emulator = TcpTransport::connect(address).unwrap();
+        daemon.insert(slow.clone(), Ledger::Tcp(emulator)).await;
+        daemon
+            .insert(fast.clone(), Ledger::Mock(mock.clone()))
+            .await;
+
+        with_daemon(&daemon, |path| async move {
+            let stuck = async {
+                let ledger = Ledger::Remote(RemoteTransport::connect(&path, &slow).await.unwrap());
+                ledger.exchange(&command(0x01)).await
+            };
+            let other = async {
+                let ledger = Ledger::Remote(RemoteTransport::connect(&path, &fast).await.unwrap());
+                on_received.await.unwrap();
+                // answered while the emulator still waits, or the emulator
+                // hangs up on the stuck client first
+                ledger.exchange(&command(0x02)).await.unwrap();
+                release.send(()).unwrap();
+            };
+            let (stuck, ()) = tokio::join!(stuck, other);
+            stuck.unwrap();
+        })
+        .await;
+        mock.verify().unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+//! Client for the BOLOS management commands understood by the dashboard and,
+//! for [Dashboard::app_and_version] and [Dashboard::quit_app], by every app.
+
+use ledger_transport::APDUCommand;
+
+use crate::{apdu::ResponseReader, error::LedgerUtilityError, status, Ledger};
+
+const CLA_BOLOS: u8 = 0xe0;
+const CLA_APP: u8 = 0xb0;
+const INS_GET_VERSION: u8 = 0x01;
+const INS_OPEN_APP: u8 = 0xd8;
+const INS_QUIT_APP: u8 = 0xa7;
+const INS_LIST_APPS_FIRST: u8 = 0xde;
+const INS_LIST_APPS_NEXT: u8 = 0xdf;
+
+/// Firmware versions reported by the dashboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareInfo {
+    pub target_id: u32,
+    pub se_version: String,
+    pub flags: Vec<u8>,
+    pub mcu_version: String,
+}
+
+/// The app currently running on the device, `BOLOS` when the dashboard is open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppInfo {
+    pub name: String,
+    pub version: String,
+    pub flags: Vec<u8>,
+}
+
+impl AppInfo {
+    /// Whether this is the dashboard rather than an app
+    pub fn is_dashboard(&self) -> bool {
+        self.name == "BOLOS"
+    }
+}
+
+/// An app installed on the device, as listed by the dashboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledApp {
+    pub name: String,
+    pub flags: u16,
+    /// Size of the app in flash blocks
+    pub blocks: u16,
+    pub code_hash: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+/// Issues dashboard commands over a borrowed [Ledger]
+pub struct Dashboard<'a> {
+    ledger: &'a Ledger,
+    /// Whether the commands are sent within a [crate::Session] already holding the ledger
+    in_session: bool,
+}
+
+impl<'a> Dashboard<'a> {
+    pub(crate) fn new(ledger: &'a Ledger) -> Self {
+        Self {
+            ledger,
+            in_session: false,
+        }
+    }
+
+    /// Send the commands without locking `ledger`, whose session the caller holds
+    pub(crate) fn unlocked(ledger: &'a Ledger) -> Self {
+        Self {
+            ledger,
+            in_session: true,
+        }
+    }
+
+    /// Read the firmware versions. Only answered while the dashboard is open.
+    pub async fn device_info(&self) -> Result<FirmwareInfo, LedgerUtilityError> {
+        let data = self.send(CLA_BOLOS, INS_GET_VERSION, vec![]).await?;
+        let mut reader = ResponseReader::new(&data);
+        let target_id = reader.u32()?;
+        let se_version = reader.string()?;
+        let flags = reader.field()?.to_vec();
+        // some firmwares null terminate the MCU version
+        let mcu_version = reader.string()?.trim_end_matches('\0').to_string();
+        Ok(FirmwareInfo {
+            target_id,
+            se_version,
+            flags,
+            mcu_version,
+        })
+    }
+
+    /// Read the name and version of the running app
+    pub async fn app_and_version(&self) -> Result<AppInfo, LedgerUtilityError> {
+        let data = self.send(CLA_APP, INS_GET_VERSION, vec![]).await?;
+        let mut reader = ResponseReader::new(&data);
+        let format = reader.u8()?;
+        if format != 1 {
+            return Err(LedgerUtilityError::MalformedAnswer(format!(
+                "unknown app info format {}",
+                format
+            )));
+        }
+        Ok(AppInfo {
+            name: reader.string()?,
+            version: reader.string()?,
+            flags: reader.field().map(<[u8]>::to_vec).unwrap_or_default(),
+        })
+    }
+
+    /// List the installed apps. The user has to allow the request on the device,
+    /// and firmwares that require a secure channel reject it.
+    pub async fn list_apps(&self) -> Result<Vec<InstalledApp>, LedgerUtilityError> {
+        let mut apps = Vec::new();
+        let mut data = self.send(CLA_BOLOS, INS_LIST_APPS_FIRST, vec![]).await?;
+        // every answer holds a batch of apps, an empty answer ends the list
+        while !data.is_empty() {
+            let mut reader = ResponseReader::new(&data);
+            if reader.u8()? != 1 {
+                return Err(LedgerUtilityError::MalformedAnswer(String::from(
+                    "unknown app list format",
+                )));
+            }
+            while !reader.is_empty() {
+                // length of the entry, implied by its fields
+                reader.take(1)?;
+                let blocks = reader.u16()?;
+                let flags = reader.u16()?;
+                let code_hash = reader.take(32)?.to_vec();
+                let hash = reader.take(32)?.to_vec();
+                apps.push(InstalledApp {
+                    name: reader.string()?,
+                    flags,
+                    blocks,
+                    code_hash,
+                    hash,
+                });
+            }
+            data = self.send(CLA_BOLOS, INS_LIST_APPS_NEXT, vec![]).await?;
+        }
+        Ok(apps)
+    }
+
+    /// Ask the dashboard to open the app called `name`. The user may have to
+    /// confirm on the device, and the device re-enumerates once the app starts.
+    pub async fn open_app(&self, name: &str) -> Result<(), LedgerUtilityError> {
+        self.send(CLA_BOLOS, INS_OPEN_APP, name.as_bytes().to_vec())
+            .await
+            .map(drop)
+    }
+
+    /// Quit the running app and return to the dashboard
+    pub async fn quit_app(&self) -> Result<(), LedgerUtilityError> {
+        self.send(CLA_APP, INS_QUIT_APP, vec![]).await.map(drop)
+    }
+
+    async fn send(&self, cla: u8, ins: u8, data: Vec<u8>) -> Result<Vec<u8>, LedgerUtilityError> {
+        let command = APDUCommand {
+            cla,
+            ins,
+            p1: 0,
+            p2: 0,
+            data,
+        };
+        let answer = match self.in_session {
+            true => status::check(self.ledger.exchange_unlocked(&command).await?)?,
+            false => self.ledger.exchange_checked(&command).await?,
+        };
+        Ok(answer.data().to_vec())
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use super::*;
+    use crate::{
+        mock::{Expectation, MockTransport},
+        status::ApduStatus,
+    };
+
+    #[tokio::test]
+    async fn test_device_info() {
+        let mock = MockTransport::new();
+        let mut answer = vec![0x33, 0x10, 0x00, 0x04, 5];
+        answer.extend_from_slice(b"2.1.0");
+        answer.extend_from_slice(&[4, 0xa6, 0x00, 0x00, 0x00, 5]);
+        answer.extend_from_slice(b"5.24\0");
+        mock.expect(
+            Expectation::new()
+                .cla(0xe0)
+                .ins(0x01)
+                .respond(answer, 0x9000),
+        );
+        let ledger = Ledger::Mock(mock.clone());
+
+        let info = ledger.dashboard().device_info().await.unwrap();
+        assert_eq!(
+            info,
+            FirmwareInfo {
+                target_id: 0x33100004,
+                se_version: String::from("2.1.0"),
+                flags: vec![0xa6, 0, 0, 0],
+                mcu_version: String::from("5.24"),
+            }
+        );
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_app_and_version() {
+        let mock = MockTransport::new();
+        let mut answer = vec![1, 6];
+        answer.extend_from_slice(b"Cosmos");
+        answer.push(6);
+        answer.extend_from_slice(b"2.35.0");
+        answer.extend_from_slice(&[1, 0x02]);
+        mock.expect(
+            Expectation::new()
+                .cla(0xb0)
+                .ins(0x01)
+                .respond(answer, 0x9000),
+        )
+        .expect(
+            Expectation::new()
+                .cla(0xb0)
+                .ins(0x01)
+                .respond(vec![1], 0x9000),
+        );
+        let ledger = Ledger::Mock(mock.clone());
+
+        let app = ledger.dashboard().app_and_version().await.unwrap();
+        assert_eq!(app.name, "Cosmos");
+        assert_eq!(app.version, "2.35.0");
+        assert_eq!(app.flags, vec![0x02]);
+        assert!(!app.is_dashboard());
+
+        let error = ledger.dashboard().app_and_version().await.unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::MalformedAnswer(_)));
+    }
+
+    #[tokio::test]
+    async fn test_list_apps() {
+        let entry = |name: &str| {
+            let mut entry = vec![70 + name.len() as u8, 0x00, 0x10, 0x0a, 0x50];
+            entry.extend_from_slice(&[0x11; 32]);
+            entry.extend_from_slice(&[0x22; 32]);
+            entry.push(name.len() as u8);
+            entry.extend_from_slice(name.as_bytes());
+            entry
+        };
+        let mock = MockTransport::new();
+        let mut first = vec![1];
+        first.extend(entry("Bitcoin"));
+        first.extend(entry("Cosmos"));
+        let mut second = vec![1];
+        second.extend(entry("Ethereum"));
+        mock.expect(Expectation::new().ins(0xde).respond(first, 0x9000))
+            .expect(Expectation::new().ins(0xdf).respond(second, 0x9000))
+            .expect(Expectation::new().ins(0xdf));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let apps = ledger.dashboard().list_apps().await.unwrap();
+        let names: Vec<&str> = apps.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["Bitcoin", "Cosmos", "Ethereum"]);
+        assert_eq!(apps[0].blocks, 0x10);
+        assert_eq!(apps[0].flags, 0x0a50);
+        assert_eq!(apps[0].hash, vec![0x22; 32]);
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_and_quit_app() {
+        let mock = MockTransport::new();
+        mock.expect(
+            Expectation::new()
+                .cla(0xe0)
+                .ins(0xd8)
+                .data(b"Cosmos".to_vec())
+                .respond(vec![], 0x6807),
+        )
+        .expect(Expectation::new().cla(0xb0).ins(0xa7));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let error = ledger.dashboard().open_app("Cosmos").await.unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::Apdu {
+                status: ApduStatus::AppNotInstalled,
+                ..
+            }
+        ));
+        ledger.dashboard().quit_app().await.unwrap();
+        mock.verify().unwrap();
+    }
+}
@@ -15,6 +15,8 @@ use ledger_transport_hid::LedgerHIDError;
 ********************************************************************************/
 use thiserror::Error;
 
+use crate::{status::ApduStatus, Transport};
+
 #[derive(Error, Debug)]
 pub enum LedgerUtilityError {
     /// Error from the HID transport
@@ -23,7 +25,127 @@ pub enum LedgerUtilityError {
     /// Error from the bluetooth transport
     #[error("{0}")]
     Ble(#[from] LedgerBleError),
+    /// Error from the bluetooth adapter
+    #[error("{0}")]
+    Btleplug(#[from] btleplug::Error),
+
+    /// Error from the TCP transport
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    /// Error from a transport registered with [crate::ConnectionBuilder::backend]
+    #[error("{0}")]
+    Custom(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("No device found")]
     DeviceNotFound,
+    /// A device that re-enumerated under a new id could not be told apart from
+    /// other devices of the same model
+    #[error("{0} devices match the reopened ledger, disconnect the others")]
+    AmbiguousDevice(usize),
+    /// The ledger was not opened from a [crate::Device], so there is nothing to reopen it from
+    #[error("Ledger has no device id to reopen it by")]
+    MissingDeviceId,
+    /// A retried operation ran out of attempts or time
+    #[error("Timed out after {attempts} attempt(s){}", describe_last(.last))]
+    Timeout {
+        attempts: u32,
+        #[source]
+        last: Option<Box<LedgerUtilityError>>,
+    },
+    /// The device did not answer an exchange in time
+    #[error("No answer after {0:?}")]
+    ExchangeTimeout(std::time::Duration),
+    /// The user did not confirm or reject on the device in time
+    #[error("No confirmation from the user after {0:?}")]
+    ConfirmationTimeout(std::time::Duration),
+    #[error("Exchange cancelled")]
+    Cancelled,
+    #[error("Timed out after {0:?} waiting for the session")]
+    SessionTimeout(std::time::Duration),
+    #[error("Invalid device id: {0}")]
+    InvalidDeviceId(String),
+    /// Invalid name pattern in a device filter
+    #[error("{0}")]
+    Regex(#[from] regex::Error),
+    #[error("Bluetooth peripheral properties are unavailable")]
+    PropertiesUnavailable,
+    #[error("{0} transport is not available")]
+    TransportUnavailable(Transport),
+    /// None of the enabled transports could be initialized
+    #[error("No transport could be initialized{}", describe_init_errors(.0))]
+    NoTransport(Vec<(Transport, LedgerUtilityError)>),
+    /// The device answered with a status word other than success
+    #[error("APDU error: {status}")]
+    Apdu { status: ApduStatus, data: Vec<u8> },
+    #[error("Invalid APDU: {0}")]
+    InvalidApdu(String),
+    #[error("Invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    /// The data of an answer does not have the expected layout
+    #[error("Malformed APDU answer: {0}")]
+    MalformedAnswer(String),
+    /// A different app than the requested one is running
+    #[error("Expected app {expected} to be running, found {running}")]
+    WrongApp { expected: String, running: String },
+    #[error("{name} {version} does not satisfy the required version {required}")]
+    IncompatibleAppVersion {
+        name: String,
+        version: semver::Version,
+        required: semver::VersionReq,
+    },
+    /// An app reported a version that is not valid semver
+    #[error("{0}")]
+    Semver(#[from] semver::Error),
+    #[error("APDU answer was shorter than the status word")]
+    AnswerTooShort,
+    /// A mock transport received a command that does not match its script
+    #[error("Unexpected APDU at step {step}: {command}")]
+    UnexpectedApdu { step: usize, command: String },
+    /// A mock or replay transport was verified before its script was consumed
+    #[error("{0} expected APDU(s) were never sent")]
+    UnmetExpectations(usize),
+    /// A replayed session sent a different command than the one recorded
+    #[error("Replay diverged at step {step}: expected {expected}, got {actual}")]
+    ReplayDiverged {
+        step: usize,
+        expected: String,
+        actual: String,
+    },
+    #[error("Invalid recording at line {line}: {reason}")]
+    InvalidRecording { line: usize, reason: String },
+    /// A replayed exchange failed with this error when it was recorded
+    #[error("Recorded exchange failed: {0}")]
+    RecordedError(String),
+    /// The daemon reported an error or broke the protocol
+    #[error("Daemon error: {0}")]
+    Remote(String),
+    #[error("Invalid script at line {line}: {reason}")]
+    InvalidScript { line: usize, reason: String },
+    /// A step of an APDU script did not get the expected answer
+    #[error("Script failed at line {line}: {reason}")]
+    ScriptFailed { line: usize, reason: String },
+}
+
+impl LedgerUtilityError {
+    /// Whether the error comes from the link to the device rather than from
+    /// the device or this crate, so that reopening the device may help
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Self::Hid(_) | Self::Ble(_) | Self::Btleplug(_) | Self::Io(_) | Self::Custom(_)
+        )
+    }
+}
+
+fn describe_init_errors(errors: &[(Transport, LedgerUtilityError)]) -> String {
+    errors
+        .iter()
+        .map(|(transport, error)| format!("; {}: {}", transport, error))
+        .collect()
+}
+
+fn describe_last(last: &Option<Box<LedgerUtilityError>>) -> String {
+    last.as_ref()
+        .map(|error| format!(": {}", error))
+        .unwrap_or_default()
 }
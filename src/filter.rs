@@ -0,0 +1,71 @@
+use regex::Regex;
+
+use crate::{error::LedgerUtilityError, info::DeviceInfo, model::LedgerModel, Transport};
+
+/// Selects devices by their [DeviceInfo]. Criteria that are not set match any device.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    model: Option<LedgerModel>,
+    transport: Option<Transport>,
+    name: Option<Regex>,
+}
+
+impl DeviceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn model(mut self, model: LedgerModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Only match devices whose product string or bluetooth name matches `pattern`
+    pub fn name(mut self, pattern: &str) -> Result<Self, LedgerUtilityError> {
+        self.name = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    pub fn matches(&self, info: &DeviceInfo) -> bool {
+        self.model.is_none_or(|model| info.model == Some(model))
+            && self
+                .transport
+                .is_none_or(|transport| info.transport == transport)
+            && self.name.as_ref().is_none_or(|name| {
+                info.product
+                    .as_deref()
+                    .is_some_and(|product| name.is_match(product))
+            })
+    }
+}
+
+#[cfg(all(test, feature = "usb"))]
+mod test {
+    use super::*;
+    use crate::info::DeviceId;
+
+    #[test]
+    fn test_matches() {
+        let mut info = DeviceInfo::new(DeviceId::Usb(String::from("/dev/hidraw0")));
+        info.product = Some(String::from("Nano S Plus"));
+        info.model = Some(LedgerModel::NanoSPlus);
+
+        assert!(DeviceFilter::new().matches(&info));
+        assert!(DeviceFilter::new()
+            .transport(Transport::Usb)
+            .model(LedgerModel::NanoSPlus)
+            .name("^Nano S")
+            .unwrap()
+            .matches(&info));
+        assert!(!DeviceFilter::new().model(LedgerModel::NanoX).matches(&info));
+        assert!(!DeviceFilter::new().name("Stax").unwrap().matches(&info));
+
+        info.product = None;
+        assert!(!DeviceFilter::new().name(".*").unwrap().matches(&info));
+    }
+}
@@ -0,0 +1,166 @@
+#[cfg(feature = "tcp")]
+use std::net::SocketAddr;
+use std::{fmt::Display, str::FromStr};
+
+use crate::{error::LedgerUtilityError, model::LedgerModel, Transport};
+
+/// A stable identifier of a [crate::Device] that does not require talking to it.
+///
+/// USB devices are identified by their HID path rather than their serial
+/// number, because Ledger devices all report the same serial number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", try_from = "String")
+)]
+pub enum DeviceId {
+    /// The address of the bluetooth peripheral
+    #[cfg(feature = "bluetooth")]
+    Bluetooth(String),
+    /// The HID path of the USB interface
+    #[cfg(feature = "usb")]
+    Usb(String),
+    /// The address of the emulator
+    #[cfg(feature = "tcp")]
+    Tcp(SocketAddr),
+    /// A device of a [crate::custom::LedgerTransport], identified by the
+    /// backend's name and an id the backend chooses
+    Custom { backend: String, id: String },
+}
+
+impl DeviceId {
+    pub fn transport(&self) -> Transport {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            DeviceId::Bluetooth(_) => Transport::Bluetooth,
+            #[cfg(feature = "usb")]
+            DeviceId::Usb(_) => Transport::Usb,
+            #[cfg(feature = "tcp")]
+            DeviceId::Tcp(_) => Transport::Tcp,
+            DeviceId::Custom { .. } => Transport::Custom,
+        }
+    }
+}
+
+/// Formats as `<transport>:<address>`, e.g. `usb:/dev/hidraw3` or `tcp:127.0.0.1:9999`.
+/// Custom devices format as `custom:<backend>:<id>`.
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            DeviceId::Bluetooth(address) => write!(f, "bluetooth:{}", address),
+            #[cfg(feature = "usb")]
+            DeviceId::Usb(path) => write!(f, "usb:{}", path),
+            #[cfg(feature = "tcp")]
+            DeviceId::Tcp(address) => write!(f, "tcp:{}", address),
+            DeviceId::Custom { backend, id } => write!(f, "custom:{}:{}", backend, id),
+        }
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = LedgerUtilityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LedgerUtilityError::InvalidDeviceId(s.to_string());
+        let (transport, address) = s.split_once(':').ok_or_else(invalid)?;
+        match transport {
+            #[cfg(feature = "bluetooth")]
+            "bluetooth" => Ok(DeviceId::Bluetooth(address.to_string())),
+            #[cfg(feature = "usb")]
+            "usb" => Ok(DeviceId::Usb(address.to_string())),
+            #[cfg(feature = "tcp")]
+            "tcp" => address.parse().map(DeviceId::Tcp).map_err(|_| invalid()),
+            "custom" => {
+                let (backend, id) = address.split_once(':').ok_or_else(invalid)?;
+                Ok(DeviceId::Custom {
+                    backend: backend.to_string(),
+                    id: id.to_string(),
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl From<DeviceId> for String {
+    fn from(id: DeviceId) -> Self {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for DeviceId {
+    type Error = LedgerUtilityError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        id.parse()
+    }
+}
+
+/// Metadata about a [crate::Device], gathered without connecting to it.
+///
+/// Fields that do not apply to the device's transport are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub transport: Transport,
+    /// The USB product string or the advertised bluetooth name
+    pub product: Option<String>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial_number: Option<String>,
+    pub hid_path: Option<String>,
+    pub ble_address: Option<String>,
+    pub rssi: Option<i16>,
+    pub model: Option<LedgerModel>,
+}
+
+impl DeviceInfo {
+    /// Metadata with only the id and transport known, for
+    /// [crate::custom::LedgerTransport]s to fill in
+    pub fn new(id: DeviceId) -> Self {
+        Self {
+            transport: id.transport(),
+            id,
+            product: None,
+            vendor_id: None,
+            product_id: None,
+            serial_number: None,
+            hid_path: None,
+            ble_address: None,
+            rssi: None,
+            model: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_id_round_trip() {
+        let ids = [
+            #[cfg(feature = "bluetooth")]
+            DeviceId::Bluetooth(String::from("E4:5F:01:2A:3B:4C")),
+            #[cfg(feature = "usb")]
+            DeviceId::Usb(String::from("/dev/hidraw3")),
+            #[cfg(feature = "usb")]
+            DeviceId::Usb(String::from("1-1.2:1.0")),
+            #[cfg(feature = "tcp")]
+            DeviceId::Tcp("127.0.0.1:9999".parse().unwrap()),
+            DeviceId::Custom {
+                backend: String::from("relay"),
+                id: String::from("host:3"),
+            },
+        ];
+        for id in ids {
+            assert_eq!(id.to_string().parse::<DeviceId>().unwrap(), id);
+        }
+        assert!("serial:0001".parse::<DeviceId>().is_err());
+        assert!("usb".parse::<DeviceId>().is_err());
+        assert!("custom:relay".parse::<DeviceId>().is_err());
+    }
+}
@@ -1,29 +1,116 @@
-use std::{fmt::Debug, ops::Deref};
+#[cfg(feature = "tcp")]
+use std::net::SocketAddr;
+#[cfg(feature = "usb")]
+use std::sync::Mutex;
+use std::{
+    fmt::{Debug, Display},
+    ops::Deref,
+    sync::Arc,
+    time::Duration,
+};
 
 #[cfg(feature = "bluetooth")]
-use btleplug::{api::Peripheral, platform};
+use btleplug::{
+    api::{Central, CentralEvent, Manager},
+    platform,
+};
+use chunk::ChunkMarkers;
+use custom::{CustomDevice, CustomTransport, LedgerTransport};
+use dashboard::Dashboard;
 use error::LedgerUtilityError;
+use filter::DeviceFilter;
+use futures::Stream;
+#[cfg(feature = "bluetooth")]
+use futures::{stream, StreamExt};
+use info::{DeviceId, DeviceInfo};
 #[cfg(feature = "bluetooth")]
 use ledger_bluetooth::TransportNativeBle;
 
 use ledger_transport::{async_trait, APDUAnswer, APDUCommand, Exchange};
 #[cfg(feature = "usb")]
 use ledger_transport_hid::{
-    hidapi::{DeviceInfo, HidApi},
-    TransportNativeHID,
+    hidapi::{DeviceInfo as HidDeviceInfo, HidApi},
+    LedgerHIDError, TransportNativeHID,
 };
+use model::{LedgerModel, MAX_APDU_PAYLOAD};
+use retry::RetryPolicy;
+use semver::{Version, VersionReq};
+use session::{Session, SessionGuard, SessionLock};
+use timeout::ExchangeLimits;
+use watch::{DeviceEvent, WATCH_INTERVAL};
 
+pub mod apdu;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "bluetooth")]
+pub mod bluetooth;
+pub mod chunk;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod custom;
+#[cfg(all(feature = "daemon", unix))]
+pub mod daemon;
+pub mod dashboard;
 pub mod error;
+pub mod filter;
+pub mod info;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod model;
+pub mod path;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod resilient;
+pub mod retry;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod session;
+#[cfg(feature = "metrics")]
+pub mod stats;
+pub mod status;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(test)]
+mod test_util;
+pub mod timeout;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod watch;
+
+#[cfg(feature = "bluetooth")]
+use bluetooth::BluetoothTransport;
+#[cfg(all(feature = "daemon", unix))]
+use daemon::{RemoteSession, RemoteTransport};
+#[cfg(feature = "mock")]
+use mock::MockTransport;
+#[cfg(feature = "replay")]
+use replay::{Recorder, ReplayTransport};
+#[cfg(feature = "tcp")]
+use tcp::TcpTransport;
+#[cfg(feature = "usb")]
+use usb::UsbTransport;
 
-#[cfg(not(feature = "bluetooth"))]
-#[cfg(not(feature = "usb"))]
-compile_error!("You must enable at least one transport feature: bluetooth or usb");
+#[cfg(not(any(feature = "bluetooth", feature = "usb", feature = "tcp")))]
+compile_error!("You must enable at least one transport feature: bluetooth, usb or tcp");
 
 pub enum Ledger {
     #[cfg(feature = "bluetooth")]
-    Bluetooth(TransportNativeBle),
+    Bluetooth(BluetoothTransport),
     #[cfg(feature = "usb")]
-    Usb(TransportNativeHID),
+    Usb(UsbTransport),
+    #[cfg(feature = "tcp")]
+    Tcp(TcpTransport),
+    #[cfg(feature = "mock")]
+    Mock(MockTransport),
+    #[cfg(feature = "replay")]
+    Replay(ReplayTransport),
+    #[cfg(feature = "replay")]
+    Recording(Recorder),
+    #[cfg(all(feature = "daemon", unix))]
+    Remote(RemoteTransport),
+    Custom(CustomTransport),
 }
 
 #[async_trait]
@@ -31,18 +118,419 @@ impl Exchange for Ledger {
     type Error = LedgerUtilityError;
     type AnswerType = Vec<u8>;
 
+    /// Exchange a single command, waiting for any [Session] in progress to end
     async fn exchange<I>(
         &self,
         command: &APDUCommand<I>,
     ) -> Result<APDUAnswer<Self::AnswerType>, Self::Error>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        let _session = self.session_lock().lock().await;
+        self.exchange_unlocked(command).await
+    }
+}
+
+impl Ledger {
+    /// Exchange `command` without taking the session lock
+    pub(crate) async fn exchange_unlocked<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        let exchange = self.exchange_transport(command);
+        #[cfg(feature = "tracing")]
+        let exchange = trace::instrument(self, command, exchange);
+        #[cfg(feature = "metrics")]
+        let exchange = stats::instrument(self, command, exchange);
+        exchange.await
+    }
+
+    /// Exchange `command` over the transport alone. A recorder calls it for the
+    /// ledger it wraps, so the exchange is only instrumented once, as a
+    /// recording.
+    pub(crate) async fn exchange_transport<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            Ledger::Bluetooth(transport) => transport.exchange(command).await,
+            #[cfg(feature = "usb")]
+            Ledger::Usb(transport) => transport.exchange(command),
+            #[cfg(feature = "tcp")]
+            Ledger::Tcp(transport) => transport.exchange(command),
+            #[cfg(feature = "mock")]
+            Ledger::Mock(transport) => transport.exchange(command),
+            #[cfg(feature = "replay")]
+            Ledger::Replay(transport) => transport.exchange(command),
+            #[cfg(feature = "replay")]
+            Ledger::Recording(recorder) => Box::pin(recorder.exchange(command)).await,
+            #[cfg(all(feature = "daemon", unix))]
+            Ledger::Remote(transport) => transport.exchange(command).await,
+            Ledger::Custom(transport) => transport.exchange(command).await,
+        }
+    }
+
+    /// Like [Ledger::exchange_transport], but the blocking transports, also
+    /// when recorded, exchange on a separate thread, and remote ledgers on a
+    /// task of their own, so the returned future can be dropped mid-exchange. `guard` is released once the transport is done
+    /// with the device, so an abandoned exchange keeps later ones waiting
+    /// instead of blocking them on the transport. It is `None` if the caller
+    /// holds the session for longer than this exchange.
+    pub(crate) async fn exchange_detached<I>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
     where
         I: Deref<Target = [u8]> + Send + Sync,
     {
+        match self {
+            #[cfg(feature = "usb")]
+            Ledger::Usb(transport) => transport.exchange_detached(command, guard).await,
+            #[cfg(feature = "tcp")]
+            Ledger::Tcp(transport) => transport.exchange_detached(command, guard).await,
+            #[cfg(all(feature = "daemon", unix))]
+            Ledger::Remote(transport) => transport.exchange_detached(command, guard).await,
+            #[cfg(feature = "replay")]
+            Ledger::Recording(recorder) => {
+                Box::pin(recorder.exchange_detached(command, guard)).await
+            }
+            _ => {
+                let _guard = guard;
+                self.exchange_transport(command).await
+            }
+        }
+    }
+
+    /// Like [Ledger::exchange_unlocked], but the blocking transports exchange
+    /// on a separate thread so the returned future can be dropped mid-exchange
+    pub(crate) async fn exchange_interruptible<I>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        let exchange = self.exchange_detached(command, guard);
+        #[cfg(feature = "tracing")]
+        let exchange = trace::instrument(self, command, exchange);
+        #[cfg(feature = "metrics")]
+        let exchange = stats::instrument(self, command, exchange);
+        exchange.await
+    }
+
+    /// Short name of the transport, for spans and metrics
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            Ledger::Bluetooth(_) => "bluetooth",
+            #[cfg(feature = "usb")]
+            Ledger::Usb(_) => "usb",
+            #[cfg(feature = "tcp")]
+            Ledger::Tcp(_) => "tcp",
+            #[cfg(feature = "mock")]
+            Ledger::Mock(_) => "mock",
+            #[cfg(feature = "replay")]
+            Ledger::Replay(_) => "replay",
+            #[cfg(feature = "replay")]
+            Ledger::Recording(_) => "recording",
+            #[cfg(all(feature = "daemon", unix))]
+            Ledger::Remote(_) => "remote",
+            Ledger::Custom(_) => "custom",
+        }
+    }
+
+    /// The daemon connection of a ledger shared by a [daemon::Daemon], also when recorded
+    #[cfg(all(feature = "daemon", unix))]
+    fn remote(&self) -> Option<&RemoteTransport> {
+        match self {
+            Ledger::Remote(transport) => Some(transport),
+            #[cfg(feature = "replay")]
+            Ledger::Recording(recorder) => recorder.inner().remote(),
+            _ => None,
+        }
+    }
+
+    /// Hold the device on the daemon sharing it, see [Ledger::remote]
+    #[cfg(all(feature = "daemon", unix))]
+    pub(crate) async fn lock_remote(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<RemoteSession<'_>>, LedgerUtilityError> {
+        match self.remote() {
+            Some(remote) => remote.lock(timeout).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn session_lock(&self) -> &SessionLock {
         match self {
             #[cfg(feature = "bluetooth")]
-            Ledger::Bluetooth(transport) => Ok(transport.exchange(command).await?),
+            Ledger::Bluetooth(transport) => &transport.session,
             #[cfg(feature = "usb")]
-            Ledger::Usb(transport) => Ok(transport.exchange(command)?),
+            Ledger::Usb(transport) => &transport.session,
+            #[cfg(feature = "tcp")]
+            Ledger::Tcp(transport) => &transport.session,
+            #[cfg(feature = "mock")]
+            Ledger::Mock(transport) => &transport.session,
+            #[cfg(feature = "replay")]
+            Ledger::Replay(transport) => &transport.session,
+            // a recording shares the session of the ledger it records
+            #[cfg(feature = "replay")]
+            Ledger::Recording(recorder) => recorder.inner().session_lock(),
+            #[cfg(all(feature = "daemon", unix))]
+            Ledger::Remote(transport) => &transport.session,
+            Ledger::Custom(transport) => &transport.session,
+        }
+    }
+
+    /// Take exclusive use of this ledger for a sequence of exchanges, waiting
+    /// for the sessions and exchanges queued before it. A ledger shared by a
+    /// [daemon::Daemon] is also held for this process by the daemon, which
+    /// fails if the daemon cannot be reached.
+    pub async fn session(&self) -> Result<Session<'_>, LedgerUtilityError> {
+        Session::acquire(self, None).await
+    }
+
+    /// Like [Ledger::session], but give up with [LedgerUtilityError::SessionTimeout]
+    /// if the ledger is not available within `timeout`
+    pub async fn session_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Session<'_>, LedgerUtilityError> {
+        Session::acquire(self, Some(timeout)).await
+    }
+
+    /// Exchange `command`, failing with [LedgerUtilityError::ExchangeTimeout] if
+    /// it is not answered within `timeout`, including the time spent waiting for
+    /// a [Session] in progress
+    pub async fn exchange_with_timeout<I>(
+        &self,
+        command: &APDUCommand<I>,
+        timeout: Duration,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.exchange_with_limits(command, &ExchangeLimits::new().timeout(timeout))
+            .await
+    }
+
+    /// Exchange `command` within the timeouts and cancellation of `limits`.
+    ///
+    /// USB and TCP reads cannot be interrupted, so they run on a blocking thread
+    /// that finishes on its own once the device answers; until then later
+    /// exchanges with the device wait for it.
+    pub async fn exchange_with_limits<I>(
+        &self,
+        command: &APDUCommand<I>,
+        limits: &ExchangeLimits,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        limits
+            .run(async {
+                let guard = self.session_lock().lock_owned().await;
+                self.exchange_interruptible(command, Some(guard)).await
+            })
+            .await
+    }
+
+    /// Exchange `command` and turn any status word other than `0x9000` into
+    /// [LedgerUtilityError::Apdu]
+    pub async fn exchange_checked<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        status::check(self.exchange(command).await?)
+    }
+
+    /// Send a payload too large for a single APDU as a sequence of APDUs.
+    ///
+    /// The data of `command` is split into chunks of [Ledger::max_apdu_payload]
+    /// bytes, each sent with the CLA, INS and P2 of `command` and the P1 given by
+    /// `markers` for its position. Stops at the first status word other than
+    /// success and returns the answer to the last chunk. The whole message is
+    /// sent within a single [Session].
+    pub async fn exchange_chunked<I>(
+        &self,
+        command: &APDUCommand<I>,
+        markers: ChunkMarkers,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.session()
+            .await?
+            .exchange_chunked(command, markers)
+            .await
+    }
+
+    /// Management commands of the device dashboard
+    pub fn dashboard(&self) -> Dashboard<'_> {
+        Dashboard::new(self)
+    }
+
+    /// Make sure the app called `name`, in a version matching `version_req`, is
+    /// running and return a ledger connected to it.
+    ///
+    /// A different running app is quit and the requested one opened from the
+    /// dashboard, which may require the user to confirm on the device. USB and
+    /// bluetooth devices re-enumerate whenever the app changes, so they, and
+    /// devices of custom backends, are rediscovered through `connection`
+    /// according to `retry_policy`; the returned ledger replaces this one. A
+    /// device that comes back under a new id is only recognized if no other
+    /// device of its model is attached, otherwise the attempts fail with
+    /// [LedgerUtilityError::AmbiguousDevice].
+    pub async fn ensure_app(
+        self,
+        connection: &Connection,
+        name: &str,
+        version_req: &VersionReq,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Ledger, LedgerUtilityError> {
+        let retry_policy = retry_policy.into();
+        let mut ledger = self;
+
+        let mut app = ledger.dashboard().app_and_version().await?;
+        if app.name != name {
+            if !app.is_dashboard() {
+                ledger.dashboard().quit_app().await?;
+                ledger = ledger.reopen(connection, "BOLOS", &retry_policy).await?;
+            }
+            ledger.dashboard().open_app(name).await?;
+            ledger = ledger.reopen(connection, name, &retry_policy).await?;
+            app = ledger.dashboard().app_and_version().await?;
+        }
+
+        let version = Version::parse(&app.version)?;
+        match version_req.matches(&version) {
+            true => Ok(ledger),
+            false => Err(LedgerUtilityError::IncompatibleAppVersion {
+                name: app.name,
+                version,
+                required: version_req.clone(),
+            }),
+        }
+    }
+
+    /// Wait for the device to come back running `app` after an app switch
+    async fn reopen(
+        self,
+        connection: &Connection,
+        app: &str,
+        retry_policy: &RetryPolicy,
+    ) -> Result<Ledger, LedgerUtilityError> {
+        let info = match &self {
+            #[cfg(feature = "bluetooth")]
+            Ledger::Bluetooth(transport) => Some(transport.info().clone()),
+            #[cfg(feature = "usb")]
+            Ledger::Usb(transport) => Some(transport.info().clone()),
+            Ledger::Custom(transport) => Some(transport.info().clone()),
+            // emulators, mocks and recordings keep their handle across app switches
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+        let Some(info) = info else {
+            return Ok(self);
+        };
+        // release the device so the new interface can be opened
+        drop(self);
+
+        retry_policy
+            .retry(|| async {
+                let ledger = connection.reconnect(&info).await?;
+                let running = ledger.dashboard().app_and_version().await?;
+                match running.name == app {
+                    true => Ok(ledger),
+                    false => Err(LedgerUtilityError::WrongApp {
+                        expected: app.to_string(),
+                        running: running.name,
+                    }),
+                }
+            })
+            .await
+    }
+
+    /// Metadata of the device this ledger was opened from, if it was opened from a [Device]
+    pub fn info(&self) -> Option<&DeviceInfo> {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            Ledger::Bluetooth(transport) => Some(transport.info()),
+            #[cfg(feature = "usb")]
+            Ledger::Usb(transport) => Some(transport.info()),
+            #[cfg(feature = "tcp")]
+            Ledger::Tcp(transport) => Some(transport.info()),
+            #[cfg(feature = "mock")]
+            Ledger::Mock(_) => None,
+            #[cfg(feature = "replay")]
+            Ledger::Replay(_) => None,
+            #[cfg(feature = "replay")]
+            Ledger::Recording(recorder) => recorder.inner().info(),
+            #[cfg(all(feature = "daemon", unix))]
+            Ledger::Remote(transport) => Some(transport.info()),
+            Ledger::Custom(transport) => Some(transport.info()),
+        }
+    }
+
+    pub fn id(&self) -> Option<&DeviceId> {
+        self.info().map(|info| &info.id)
+    }
+
+    pub fn model(&self) -> Option<LedgerModel> {
+        self.info().and_then(|info| info.model)
+    }
+
+    /// The largest payload a single APDU sent to this ledger can carry
+    pub fn max_apdu_payload(&self) -> usize {
+        self.model()
+            .map_or(MAX_APDU_PAYLOAD, LedgerModel::max_apdu_payload)
+    }
+
+    /// The ATT MTU negotiated by this ledger's model, `None` if it has no bluetooth
+    /// or the model is unknown
+    pub fn ble_mtu(&self) -> Option<usize> {
+        self.model().and_then(LedgerModel::ble_mtu)
+    }
+}
+
+/// The transports a [Device] can be reached over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Transport {
+    #[cfg(feature = "bluetooth")]
+    Bluetooth,
+    #[cfg(feature = "usb")]
+    Usb,
+    #[cfg(feature = "tcp")]
+    Tcp,
+    /// A backend registered with [ConnectionBuilder::backend]
+    Custom,
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            #[cfg(feature = "bluetooth")]
+            Transport::Bluetooth => write!(f, "Bluetooth"),
+            #[cfg(feature = "usb")]
+            Transport::Usb => write!(f, "Usb"),
+            #[cfg(feature = "tcp")]
+            Transport::Tcp => write!(f, "Tcp"),
+            Transport::Custom => write!(f, "Custom"),
         }
     }
 }
@@ -51,20 +539,50 @@ pub enum Device {
     #[cfg(feature = "bluetooth")]
     Bluetooth(platform::Peripheral),
     #[cfg(feature = "usb")]
-    Usb(DeviceInfo),
+    Usb(HidDeviceInfo),
+    #[cfg(feature = "tcp")]
+    Tcp(SocketAddr),
+    Custom(CustomDevice),
 }
 
 impl Device {
+    pub fn id(&self) -> DeviceId {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            Device::Bluetooth(peripheral) => bluetooth::id(peripheral),
+            #[cfg(feature = "usb")]
+            Device::Usb(device_info) => usb::id(device_info),
+            #[cfg(feature = "tcp")]
+            Device::Tcp(address) => DeviceId::Tcp(*address),
+            Device::Custom(device) => device.info().id.clone(),
+        }
+    }
+
+    /// Gather the metadata of this device. Bluetooth devices are not connected to.
+    pub async fn info(&self) -> Result<DeviceInfo, LedgerUtilityError> {
+        match self {
+            #[cfg(feature = "bluetooth")]
+            Device::Bluetooth(peripheral) => bluetooth::describe(peripheral).await,
+            #[cfg(feature = "usb")]
+            Device::Usb(device_info) => Ok(usb::describe(device_info)),
+            #[cfg(feature = "tcp")]
+            Device::Tcp(address) => Ok(DeviceInfo::new(DeviceId::Tcp(*address))),
+            Device::Custom(device) => Ok(device.info().clone()),
+        }
+    }
+
+    pub async fn model(&self) -> Result<Option<LedgerModel>, LedgerUtilityError> {
+        Ok(self.info().await?.model)
+    }
+
     pub async fn name(&self) -> Result<String, LedgerUtilityError> {
         match self {
             #[cfg(feature = "bluetooth")]
-            Device::Bluetooth(peripheral) => {
-                if !peripheral.is_connected().await.unwrap() {
-                    peripheral.connect().await.unwrap();
-                }
-                let properties = peripheral.properties().await.unwrap().unwrap();
-                let local_name = properties
-                    .local_name
+            Device::Bluetooth(_) => {
+                let local_name = self
+                    .info()
+                    .await?
+                    .product
                     .unwrap_or(String::from("(peripheral name unknown)"));
                 Ok(format!("Bluetooth: {}", local_name))
             }
@@ -73,15 +591,137 @@ impl Device {
                 "Usb: {}",
                 device_info.product_string().unwrap_or_default()
             )),
+            #[cfg(feature = "tcp")]
+            Device::Tcp(address) => Ok(format!("Tcp: {}", address)),
+            Device::Custom(device) => {
+                let info = device.info();
+                match &info.product {
+                    Some(product) => Ok(format!("{}: {}", device.backend(), product)),
+                    None => Ok(format!("{}: {}", device.backend(), info.id)),
+                }
+            }
+        }
+    }
+}
+
+/// Selects which of the compiled-in transports a [Connection] initializes
+#[derive(Debug, Clone)]
+pub struct ConnectionBuilder {
+    #[cfg(feature = "bluetooth")]
+    bluetooth: bool,
+    #[cfg(feature = "usb")]
+    usb: bool,
+    #[cfg(feature = "tcp")]
+    tcp: Vec<SocketAddr>,
+    backends: Vec<Arc<dyn LedgerTransport>>,
+}
+
+#[cfg_attr(
+    not(any(feature = "bluetooth", feature = "usb")),
+    allow(clippy::derivable_impls)
+)]
+impl Default for ConnectionBuilder {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "bluetooth")]
+            bluetooth: true,
+            #[cfg(feature = "usb")]
+            usb: true,
+            #[cfg(feature = "tcp")]
+            tcp: Vec::new(),
+            backends: Vec::new(),
+        }
+    }
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(feature = "bluetooth")]
+    pub fn bluetooth(mut self, enabled: bool) -> Self {
+        self.bluetooth = enabled;
+        self
+    }
+
+    #[cfg(feature = "usb")]
+    pub fn usb(mut self, enabled: bool) -> Self {
+        self.usb = enabled;
+        self
+    }
+
+    /// Register an emulator endpoint (Speculos, Zemu) to be listed alongside physical devices
+    #[cfg(feature = "tcp")]
+    pub fn tcp_endpoint(mut self, address: SocketAddr) -> Self {
+        self.tcp.push(address);
+        self
+    }
+
+    /// Register a transport implemented outside this crate, whose devices are
+    /// listed alongside the built-in ones
+    pub fn backend(mut self, backend: impl LedgerTransport + 'static) -> Self {
+        self.backends.push(Arc::new(backend));
+        self
+    }
+
+    /// Initialize every enabled transport.
+    ///
+    /// Succeeds as long as at least one transport is usable. Transports that
+    /// failed to initialize are reported by [Connection::init_errors].
+    pub async fn build(self) -> Result<Connection, LedgerUtilityError> {
+        #[cfg_attr(not(any(feature = "bluetooth", feature = "usb")), allow(unused_mut))]
+        let mut init_errors = Vec::new();
+
+        #[cfg(feature = "bluetooth")]
+        let bluetooth = match self.bluetooth {
+            true => match platform::Manager::new().await {
+                Ok(manager) => Some(manager),
+                Err(e) => {
+                    init_errors.push((Transport::Bluetooth, e.into()));
+                    None
+                }
+            },
+            false => None,
+        };
+        #[cfg(feature = "usb")]
+        let hid = match self.usb {
+            true => match HidApi::new() {
+                Ok(api) => Some(Mutex::new(api)),
+                Err(e) => {
+                    init_errors.push((Transport::Usb, LedgerHIDError::from(e).into()));
+                    None
+                }
+            },
+            false => None,
+        };
+
+        let connection = Connection {
+            #[cfg(feature = "bluetooth")]
+            bluetooth,
+            #[cfg(feature = "usb")]
+            hid,
+            #[cfg(feature = "tcp")]
+            tcp: self.tcp,
+            backends: self.backends,
+            init_errors,
+        };
+        match connection.has_transport() {
+            true => Ok(connection),
+            false => Err(LedgerUtilityError::NoTransport(connection.init_errors)),
         }
     }
 }
 
 pub struct Connection {
     #[cfg(feature = "bluetooth")]
-    bluetooth: platform::Manager,
+    bluetooth: Option<platform::Manager>,
     #[cfg(feature = "usb")]
-    hid: HidApi,
+    hid: Option<Mutex<HidApi>>,
+    #[cfg(feature = "tcp")]
+    tcp: Vec<SocketAddr>,
+    backends: Vec<Arc<dyn LedgerTransport>>,
+    init_errors: Vec<(Transport, LedgerUtilityError)>,
 }
 
 impl Debug for Connection {
@@ -89,92 +729,508 @@ impl Debug for Connection {
         let mut debug = f.debug_struct("Connection");
         #[cfg(feature = "bluetooth")]
         debug.field("bluetooth", &self.bluetooth);
+        #[cfg(feature = "usb")]
+        debug.field("usb", &self.hid.is_some());
+        #[cfg(feature = "tcp")]
+        debug.field("tcp", &self.tcp);
+        debug.field("backends", &self.backends);
+        debug.field("init_errors", &self.init_errors);
         debug.finish()
     }
 }
 
 impl Connection {
-    pub async fn new() -> Self {
-        Self {
+    /// Initialize every compiled-in transport, see [ConnectionBuilder::build]
+    pub async fn new() -> Result<Self, LedgerUtilityError> {
+        Self::builder().build().await
+    }
+
+    pub fn builder() -> ConnectionBuilder {
+        ConnectionBuilder::new()
+    }
+
+    /// The transports that were enabled but failed to initialize
+    pub fn init_errors(&self) -> &[(Transport, LedgerUtilityError)] {
+        &self.init_errors
+    }
+
+    fn has_transport(&self) -> bool {
+        [
             #[cfg(feature = "bluetooth")]
-            bluetooth: platform::Manager::new().await.unwrap(),
+            self.bluetooth.is_some(),
             #[cfg(feature = "usb")]
-            hid: HidApi::new().unwrap(),
-        }
+            self.hid.is_some(),
+            // emulator endpoints need no initialization
+            #[cfg(feature = "tcp")]
+            !self.tcp.is_empty(),
+            !self.backends.is_empty(),
+        ]
+        .contains(&true)
+    }
+
+    /// Register an emulator endpoint (Speculos, Zemu) to be listed alongside physical devices
+    #[cfg(feature = "tcp")]
+    pub fn add_tcp_endpoint(&mut self, address: SocketAddr) {
+        self.tcp.push(address);
+    }
+
+    /// Register a transport implemented outside this crate, see [ConnectionBuilder::backend]
+    pub fn add_backend(&mut self, backend: impl LedgerTransport + 'static) {
+        self.backends.push(Arc::new(backend));
     }
 
     pub async fn get_all_ledgers(&self) -> Result<Vec<Device>, LedgerUtilityError> {
         let mut ledgers = vec![];
         #[cfg(feature = "usb")]
-        ledgers.extend(TransportNativeHID::list_ledgers(&self.hid).map(|x| Device::Usb(x.clone())));
+        if let Some(hid) = &self.hid {
+            let mut hid = hid.lock().expect("HID api poisoned");
+            // the device list is a snapshot, refresh it to pick up devices plugged in since
+            hid.refresh_devices().map_err(LedgerHIDError::from)?;
+            ledgers.extend(TransportNativeHID::list_ledgers(&hid).map(|x| Device::Usb(x.clone())));
+        }
         #[cfg(feature = "bluetooth")]
-        ledgers.extend(
-            TransportNativeBle::list_ledgers(&self.bluetooth)
-                .await?
-                .into_iter()
-                .map(Device::Bluetooth),
-        );
+        if let Some(bluetooth) = &self.bluetooth {
+            ledgers.extend(
+                TransportNativeBle::list_ledgers(bluetooth)
+                    .await?
+                    .into_iter()
+                    .map(Device::Bluetooth),
+            );
+        }
+        #[cfg(feature = "tcp")]
+        ledgers.extend(self.tcp.iter().copied().map(Device::Tcp));
+        for backend in &self.backends {
+            ledgers.extend(backend.discover().await?.into_iter().map(|info| {
+                Device::Custom(CustomDevice {
+                    backend: backend.clone(),
+                    info,
+                })
+            }));
+        }
         Ok(ledgers)
     }
 
     pub async fn connect(&self, device: Device) -> Result<Ledger, LedgerUtilityError> {
+        #[cfg(feature = "metrics")]
+        return stats::connect(device.id().transport(), self.open(device)).await;
+        #[cfg(not(feature = "metrics"))]
+        self.open(device).await
+    }
+
+    async fn open(&self, device: Device) -> Result<Ledger, LedgerUtilityError> {
         match device {
             #[cfg(feature = "bluetooth")]
             Device::Bluetooth(peripheral) => {
-                let transport = TransportNativeBle::connect(peripheral).await?;
+                let transport = BluetoothTransport::connect(peripheral).await?;
                 Ok(Ledger::Bluetooth(transport))
             }
             #[cfg(feature = "usb")]
             Device::Usb(device_info) => {
-                let transport = TransportNativeHID::open_device(&self.hid, &device_info)?;
+                let hid = self
+                    .hid
+                    .as_ref()
+                    .ok_or(LedgerUtilityError::TransportUnavailable(Transport::Usb))?
+                    .lock()
+                    .expect("HID api poisoned");
+                let transport = UsbTransport::open(&hid, &device_info)?;
                 Ok(Ledger::Usb(transport))
             }
+            #[cfg(feature = "tcp")]
+            Device::Tcp(address) => Ok(Ledger::Tcp(TcpTransport::connect(address)?)),
+            Device::Custom(device) => {
+                let link = device.backend.connect(&device.info).await?;
+                Ok(Ledger::Custom(CustomTransport::new(device.info, link)))
+            }
         }
     }
 
+    /// Connect to the device whose [Device::name] is `device_name`, retrying
+    /// discovery and connection according to `retry_policy`
     pub async fn connect_with_name(
         &self,
         device_name: String,
-        num_retries: u8,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Ledger, LedgerUtilityError> {
+        let device_name = &device_name;
+        retry_policy
+            .into()
+            .retry(|| async move {
+                let mut found = None;
+                for device in self.get_all_ledgers().await? {
+                    // a device whose name cannot be read is not the one asked for
+                    if matches!(device.name().await, Ok(name) if &name == device_name) {
+                        found = Some(device);
+                        break;
+                    }
+                }
+                let device = found.ok_or(LedgerUtilityError::DeviceNotFound)?;
+
+                self.connect(device).await
+            })
+            .await
+    }
+
+    /// Connect to the device identified by `device_id`, retrying discovery and
+    /// connection according to `retry_policy`
+    pub async fn connect_with_id(
+        &self,
+        device_id: &DeviceId,
+        retry_policy: impl Into<RetryPolicy>,
     ) -> Result<Ledger, LedgerUtilityError> {
-        for _ in 0..num_retries {
-            let mut devices = self.get_all_ledgers().await?;
-            let mut names = Vec::new();
-            for device in &devices {
-                names.push(device.name().await?);
+        retry_policy
+            .into()
+            .retry(|| async {
+                let device = self
+                    .get_all_ledgers()
+                    .await?
+                    .into_iter()
+                    .find(|x| &x.id() == device_id)
+                    .ok_or(LedgerUtilityError::DeviceNotFound)?;
+                self.connect(device).await
+            })
+            .await
+    }
+
+    /// Open the device described by `info` again after it re-enumerated. USB
+    /// devices may come back under a different HID path, so they are matched by
+    /// model when their old path is gone.
+    async fn reconnect(&self, info: &DeviceInfo) -> Result<Ledger, LedgerUtilityError> {
+        let mut devices = self.get_all_ledgers().await?;
+        let index = match devices.iter().position(|x| x.id() == info.id) {
+            Some(index) => index,
+            None => {
+                // the id changed, so only a device of the same kind that
+                // cannot be mistaken for another one will do
+                let mut candidates = Vec::new();
+                for (i, device) in devices.iter().enumerate() {
+                    let Ok(found) = device.info().await else {
+                        continue;
+                    };
+                    if found.transport == info.transport && found.model == info.model {
+                        candidates.push(i);
+                    }
+                }
+                match candidates[..] {
+                    [] => return Err(LedgerUtilityError::DeviceNotFound),
+                    [index] => index,
+                    _ => return Err(LedgerUtilityError::AmbiguousDevice(candidates.len())),
+                }
             }
-            let index = match names.iter().position(|x| x == &device_name) {
-                Some(i) => i,
-                None => continue,
-            };
+        };
+        self.connect(devices.swap_remove(index)).await
+    }
+
+    /// Report devices as they are plugged in and removed, see [Connection::watch_with_interval]
+    pub fn watch(&self) -> impl Stream<Item = DeviceEvent> + '_ {
+        self.watch_with_interval(WATCH_INTERVAL)
+    }
 
-            let device = devices.swap_remove(index);
+    /// Report devices as they are plugged in and removed.
+    ///
+    /// Devices are enumerated every `interval`, and additionally whenever the
+    /// bluetooth adapter reports a peripheral being discovered or disconnected.
+    /// The devices already attached are reported as added first.
+    pub fn watch_with_interval(&self, interval: Duration) -> impl Stream<Item = DeviceEvent> + '_ {
+        let wake = watch::ticks(interval);
+        #[cfg(feature = "bluetooth")]
+        let wake = stream::select(wake, self.bluetooth_events());
+        watch::watch(self, wake)
+    }
 
-            return self.connect(device).await;
+    #[cfg(feature = "bluetooth")]
+    fn bluetooth_events(&self) -> impl Stream<Item = ()> + Send + '_ {
+        stream::once(async move {
+            let mut events = Vec::new();
+            if let Some(manager) = &self.bluetooth {
+                for adapter in manager.adapters().await.unwrap_or_default() {
+                    if let Ok(stream) = adapter.events().await {
+                        events.push(stream);
+                    }
+                }
+            }
+            stream::select_all(events)
+        })
+        .flatten()
+        .filter_map(|event| async move {
+            matches!(
+                event,
+                CentralEvent::DeviceDiscovered(_)
+                    | CentralEvent::DeviceConnected(_)
+                    | CentralEvent::DeviceDisconnected(_)
+            )
+            .then_some(())
+        })
+    }
+
+    /// List the devices matching `filter`. Devices whose metadata cannot be read are skipped.
+    pub async fn find(&self, filter: &DeviceFilter) -> Result<Vec<Device>, LedgerUtilityError> {
+        let mut found = Vec::new();
+        for device in self.get_all_ledgers().await? {
+            if matches!(device.info().await, Ok(info) if filter.matches(&info)) {
+                found.push(device);
+            }
         }
-        Err(LedgerUtilityError::DeviceNotFound)
+        Ok(found)
+    }
+
+    /// Connect to the first device matching `filter`, retrying discovery and
+    /// connection according to `retry_policy`
+    pub async fn connect_with_filter(
+        &self,
+        filter: &DeviceFilter,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Ledger, LedgerUtilityError> {
+        retry_policy
+            .into()
+            .retry(|| async {
+                let device = self
+                    .find(filter)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or(LedgerUtilityError::DeviceNotFound)?;
+                self.connect(device).await
+            })
+            .await
     }
 }
 #[cfg(test)]
 mod test {
-    use log::info;
     use serial_test::serial;
 
     use super::*;
+
+    // a tcp only connection has nothing to enumerate without an endpoint
+    #[cfg(any(feature = "bluetooth", feature = "usb"))]
     #[tokio::test]
     #[serial]
     async fn test_get_all_ledgers() {
-        let connection = Connection::new().await;
+        let connection = Connection::new().await.unwrap();
         let ledgers = connection.get_all_ledgers().await.unwrap();
         for ledger in ledgers {
-            info!("{}", ledger.name().await.unwrap())
+            log::info!("{}", ledger.name().await.unwrap());
+            log::info!("{:?}", ledger.info().await.unwrap());
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_builder_without_transport() {
+        let builder = Connection::builder();
+        #[cfg(feature = "bluetooth")]
+        let builder = builder.bluetooth(false);
+        #[cfg(feature = "usb")]
+        let builder = builder.usb(false);
+        let error = builder.clone().build().await.unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::NoTransport(errors) if errors.is_empty()));
+
+        #[cfg(feature = "tcp")]
+        builder
+            .tcp_endpoint("127.0.0.1:9999".parse().unwrap())
+            .build()
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_exchange_chunked() {
+        use mock::{Expectation, MockTransport};
+
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().p1(0).data(vec![1; 255]))
+            .expect(Expectation::new().p1(1).data(vec![1; 255]))
+            .expect(
+                Expectation::new()
+                    .p1(2)
+                    .data(vec![1; 10])
+                    .respond(vec![0xaa], 0x9000),
+            )
+            .expect(Expectation::new().p1(0).respond(vec![], 0x6985));
+        let ledger = Ledger::Mock(mock.clone());
+        let markers = ChunkMarkers::new(0, 1, 2);
+        let command = APDUCommand {
+            cla: 0x55,
+            ins: 0x02,
+            p1: 0,
+            p2: 0,
+            data: vec![1; 520],
+        };
+
+        let answer = ledger.exchange_chunked(&command, markers).await.unwrap();
+        assert_eq!(answer.data(), &[0xaa]);
+
+        // the first rejected chunk ends the message
+        let error = ledger
+            .exchange_chunked(&command, markers)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::Apdu {
+                status: status::ApduStatus::UserRejected,
+                ..
+            }
+        ));
+        mock.verify().unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    #[serial]
+    async fn test_ensure_app() {
+        use mock::{Expectation, MockTransport};
+        use test_util::{app, connection};
+
+        let connection = connection().await;
+        let mock = MockTransport::new();
+        mock.expect(app("Bitcoin", "2.1.0"))
+            .expect(Expectation::new().cla(0xb0).ins(0xa7))
+            .expect(Expectation::new().ins(0xd8).data(b"Cosmos".to_vec()))
+            .expect(app("Cosmos", "2.35.0"))
+            .expect(app("Cosmos", "2.35.0"));
+
+        let ledger = Ledger::Mock(mock.clone())
+            .ensure_app(
+                &connection,
+                "Cosmos",
+                &VersionReq::parse("^2.30").unwrap(),
+                RetryPolicy::once(),
+            )
+            .await
+            .unwrap();
+        let error = ledger
+            .ensure_app(
+                &connection,
+                "Cosmos",
+                &VersionReq::parse("^3").unwrap(),
+                RetryPolicy::once(),
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::IncompatibleAppVersion { .. }
+        ));
+        mock.verify().unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_reopen_after_reenumeration() {
+        use std::sync::Mutex;
+
+        use custom::{LedgerLink, LedgerTransport};
+        use ledger_transport::async_trait;
+        use mock::{Expectation, MockTransport};
+        use test_util::app;
+
+        /// A backend whose devices take new ids once an app is opened, like
+        /// USB devices re-enumerating
+        struct Replug {
+            mock: MockTransport,
+            devices: Arc<Mutex<Vec<DeviceInfo>>>,
+            after_switch: Vec<DeviceInfo>,
+        }
+
+        struct ReplugLink {
+            mock: MockTransport,
+            devices: Arc<Mutex<Vec<DeviceInfo>>>,
+            after_switch: Vec<DeviceInfo>,
+        }
+
+        #[async_trait]
+        impl LedgerTransport for Replug {
+            fn name(&self) -> &str {
+                "replug"
+            }
+
+            async fn discover(&self) -> Result<Vec<DeviceInfo>, LedgerUtilityError> {
+                Ok(self.devices.lock().unwrap().clone())
+            }
+
+            async fn connect(
+                &self,
+                _device: &DeviceInfo,
+            ) -> Result<Box<dyn LedgerLink>, LedgerUtilityError> {
+                Ok(Box::new(ReplugLink {
+                    mock: self.mock.clone(),
+                    devices: self.devices.clone(),
+                    after_switch: self.after_switch.clone(),
+                }))
+            }
+        }
+
+        #[async_trait]
+        impl LedgerLink for ReplugLink {
+            async fn exchange(
+                &self,
+                command: &APDUCommand<&[u8]>,
+            ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+                if command.ins == 0xd8 {
+                    *self.devices.lock().unwrap() = self.after_switch.clone();
+                }
+                self.mock.exchange(command)
+            }
+        }
+
+        fn device(id: &str) -> DeviceInfo {
+            let mut info = DeviceInfo::new(format!("custom:replug:{}", id).parse().unwrap());
+            info.model = Some(LedgerModel::NanoSPlus);
+            info
+        }
+
+        async fn switch_app(after_switch: Vec<DeviceInfo>) -> Result<Ledger, LedgerUtilityError> {
+            let mock = MockTransport::new();
+            mock.expect(app("BOLOS", "2.1.0"))
+                .expect(Expectation::new().ins(0xd8).data(b"Cosmos".to_vec()))
+                .expect(app("Cosmos", "2.35.0"))
+                .expect(app("Cosmos", "2.35.0"));
+            let builder = Connection::builder().backend(Replug {
+                mock,
+                devices: Arc::new(Mutex::new(vec![device("1")])),
+                after_switch,
+            });
+            #[cfg(feature = "bluetooth")]
+            let builder = builder.bluetooth(false);
+            #[cfg(feature = "usb")]
+            let builder = builder.usb(false);
+            let connection = builder.build().await.unwrap();
+
+            let ledger = connection
+                .connect_with_id(&device("1").id, RetryPolicy::once())
+                .await
+                .unwrap();
+            ledger
+                .ensure_app(
+                    &connection,
+                    "Cosmos",
+                    &VersionReq::parse("^2.30").unwrap(),
+                    RetryPolicy::once(),
+                )
+                .await
+        }
+
+        let ledger = switch_app(vec![device("2")]).await.unwrap();
+        assert_eq!(ledger.id(), Some(&device("2").id));
+
+        let error = switch_app(vec![device("2"), device("3")])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::Timeout { last: Some(last), .. }
+                if matches!(*last, LedgerUtilityError::AmbiguousDevice(2))
+        ));
+    }
+
+    #[cfg(any(feature = "bluetooth", feature = "usb"))]
     #[tokio::test]
     #[serial]
     async fn test_connect() {
-        let connection = Connection::new().await;
+        let connection = Connection::new().await.unwrap();
         let mut ledgers = connection.get_all_ledgers().await.unwrap();
         if let Some(ledger) = ledgers.pop() {
             connection.connect(ledger).await.unwrap();
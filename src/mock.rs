@@ -0,0 +1,283 @@
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{error::LedgerUtilityError, session::SessionLock};
+
+/// How an [Expectation] matches the payload of a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataMatcher {
+    Any,
+    Exact(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+enum MockResponse {
+    Answer(Vec<u8>),
+    Error(LedgerUtilityError),
+}
+
+/// A single scripted step of a [MockTransport].
+///
+/// Fields that are not set match any value. Without an explicit response the
+/// expectation answers with an empty payload and `0x9000`.
+pub struct Expectation {
+    cla: Option<u8>,
+    ins: Option<u8>,
+    p1: Option<u8>,
+    p2: Option<u8>,
+    data: DataMatcher,
+    response: MockResponse,
+}
+
+impl Default for Expectation {
+    fn default() -> Self {
+        Self {
+            cla: None,
+            ins: None,
+            p1: None,
+            p2: None,
+            data: DataMatcher::Any,
+            response: MockResponse::Answer(vec![0x90, 0x00]),
+        }
+    }
+}
+
+impl Expectation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cla(mut self, cla: u8) -> Self {
+        self.cla = Some(cla);
+        self
+    }
+
+    pub fn ins(mut self, ins: u8) -> Self {
+        self.ins = Some(ins);
+        self
+    }
+
+    pub fn p1(mut self, p1: u8) -> Self {
+        self.p1 = Some(p1);
+        self
+    }
+
+    pub fn p2(mut self, p2: u8) -> Self {
+        self.p2 = Some(p2);
+        self
+    }
+
+    /// Only match commands whose payload is exactly `data`
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.data = DataMatcher::Exact(data.into());
+        self
+    }
+
+    /// Only match commands whose payload starts with `prefix`
+    pub fn data_prefix(mut self, prefix: impl Into<Vec<u8>>) -> Self {
+        self.data = DataMatcher::Prefix(prefix.into());
+        self
+    }
+
+    /// Answer a matching command with `data` followed by the status word `retcode`
+    pub fn respond(mut self, data: impl Into<Vec<u8>>, retcode: u16) -> Self {
+        let mut answer = data.into();
+        answer.extend_from_slice(&retcode.to_be_bytes());
+        self.response = MockResponse::Answer(answer);
+        self
+    }
+
+    /// Fail a matching command with `error` instead of answering it
+    pub fn fail(mut self, error: LedgerUtilityError) -> Self {
+        self.response = MockResponse::Error(error);
+        self
+    }
+
+    fn matches<I: Deref<Target = [u8]>>(&self, command: &APDUCommand<I>) -> bool {
+        let field = |expected: Option<u8>, actual: u8| expected.is_none_or(|e| e == actual);
+        field(self.cla, command.cla)
+            && field(self.ins, command.ins)
+            && field(self.p1, command.p1)
+            && field(self.p2, command.p2)
+            && match &self.data {
+                DataMatcher::Any => true,
+                DataMatcher::Exact(data) => data[..] == command.data[..],
+                DataMatcher::Prefix(prefix) => command.data.starts_with(prefix),
+            }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    expectations: VecDeque<Expectation>,
+    step: usize,
+    /// The first command that matched no expectation, as its step and description
+    unexpected: Option<(usize, String)>,
+}
+
+/// An in-process transport that answers commands from a script of [Expectation]s.
+///
+/// Expectations are consumed in the order they were added. Clones share the
+/// same script, so a test can keep a handle to call [MockTransport::verify]
+/// after moving the transport into a [crate::Ledger].
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+    pub(crate) session: Arc<SessionLock>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an expectation to the end of the script
+    pub fn expect(&self, expectation: Expectation) -> &Self {
+        self.state
+            .lock()
+            .expect("mock state poisoned")
+            .expectations
+            .push_back(expectation);
+        self
+    }
+
+    /// Check that every command was expected and every expectation of the script
+    /// has been consumed
+    pub fn verify(&self) -> Result<(), LedgerUtilityError> {
+        let state = self.state.lock().expect("mock state poisoned");
+        if let Some((step, command)) = &state.unexpected {
+            return Err(LedgerUtilityError::UnexpectedApdu {
+                step: *step,
+                command: command.clone(),
+            });
+        }
+        match state.expectations.len() {
+            0 => Ok(()),
+            remaining => Err(LedgerUtilityError::UnmetExpectations(remaining)),
+        }
+    }
+
+    pub fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let mut state = self.state.lock().expect("mock state poisoned");
+        let step = state.step;
+        state.step += 1;
+
+        // a mismatch leaves the expectation in place, and fails verify even if
+        // the caller swallows the error
+        let expectation = match state.expectations.front() {
+            Some(expectation) if expectation.matches(command) => {
+                state.expectations.pop_front().unwrap()
+            }
+            _ => {
+                let command = describe(command);
+                state.unexpected.get_or_insert((step, command.clone()));
+                return Err(LedgerUtilityError::UnexpectedApdu { step, command });
+            }
+        };
+        match expectation.response {
+            MockResponse::Answer(answer) => {
+                APDUAnswer::from_answer(answer).map_err(|_| LedgerUtilityError::AnswerTooShort)
+            }
+            MockResponse::Error(error) => Err(error),
+        }
+    }
+}
+
+fn describe<I: Deref<Target = [u8]>>(command: &APDUCommand<I>) -> String {
+    format!(
+        "cla={:02x} ins={:02x} p1={:02x} p2={:02x} data={}",
+        command.cla,
+        command.ins,
+        command.p1,
+        command.p2,
+        hex::encode(&*command.data)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use ledger_transport::Exchange;
+
+    use super::*;
+    use crate::{
+        test_util::{command, command_with},
+        Ledger,
+    };
+
+    #[tokio::test]
+    async fn test_scripted_answers() {
+        let mock = MockTransport::new();
+        mock.expect(
+            Expectation::new()
+                .cla(0xe0)
+                .ins(0x01)
+                .respond(vec![1, 2], 0x9000),
+        )
+        .expect(
+            Expectation::new()
+                .ins(0x02)
+                .data_prefix(vec![0xaa])
+                .respond(vec![], 0x6985),
+        );
+        let ledger = Ledger::Mock(mock.clone());
+
+        let answer = ledger.exchange(&command(0x01)).await.unwrap();
+        assert_eq!(answer.data(), &[1, 2]);
+        assert_eq!(answer.retcode(), 0x9000);
+
+        let answer = ledger
+            .exchange(&command_with(0x02, vec![0xaa, 0xbb]))
+            .await
+            .unwrap();
+        assert_eq!(answer.retcode(), 0x6985);
+
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_command() {
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().ins(0x01).data(vec![1]));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let error = ledger
+            .exchange(&command_with(0x01, vec![2]))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::UnexpectedApdu { step: 0, .. }
+        ));
+
+        // the expectation is still there for the right command, but the
+        // mismatch is not forgotten
+        ledger.exchange(&command_with(0x01, vec![1])).await.unwrap();
+        assert!(matches!(
+            mock.verify(),
+            Err(LedgerUtilityError::UnexpectedApdu { step: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_injected_error_and_verify() {
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().fail(LedgerUtilityError::DeviceNotFound))
+            .expect(Expectation::new());
+        let ledger = Ledger::Mock(mock.clone());
+
+        let error = ledger.exchange(&command(0x01)).await.unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::DeviceNotFound));
+        assert!(matches!(
+            mock.verify(),
+            Err(LedgerUtilityError::UnmetExpectations(1))
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::Display;
+
+use uuid::Uuid;
+
+/// The largest payload a single APDU sent to a Ledger device can carry, the
+/// same on every model: devices only accept short APDUs (ISO 7816-4), whose
+/// length is a single byte. Over bluetooth the transport splits each APDU into
+/// frames sized to the MTU of the connection, see [LedgerModel::ble_mtu].
+pub const MAX_APDU_PAYLOAD: usize = crate::apdu::MAX_DATA;
+
+/// The GATT services advertised by the bluetooth capable models
+const BLE_SERVICES: [(Uuid, LedgerModel); 3] = [
+    (
+        Uuid::from_u128(0x13d63400_2c97_0004_0000_4c6564676572),
+        LedgerModel::NanoX,
+    ),
+    (
+        Uuid::from_u128(0x13d63400_2c97_6004_0000_4c6564676572),
+        LedgerModel::Stax,
+    ),
+    (
+        Uuid::from_u128(0x13d63400_2c97_3004_0000_4c6564676572),
+        LedgerModel::Flex,
+    ),
+];
+
+/// The Ledger hardware models
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LedgerModel {
+    NanoS,
+    NanoSPlus,
+    NanoX,
+    Stax,
+    Flex,
+}
+
+impl LedgerModel {
+    /// Detect the model from the USB product ID of a Ledger device.
+    ///
+    /// Older firmwares and the bootloader report the bare model number, current
+    /// firmwares report it in the high byte and the USB interfaces in the low byte.
+    pub fn from_usb_product_id(product_id: u16) -> Option<Self> {
+        let model = match product_id {
+            0x0000..=0x00ff => product_id,
+            _ => product_id >> 8,
+        };
+        match model {
+            0x01 | 0x10 => Some(Self::NanoS),
+            0x04 | 0x40 => Some(Self::NanoX),
+            0x05 | 0x50 => Some(Self::NanoSPlus),
+            0x06 | 0x60 => Some(Self::Stax),
+            0x07 | 0x70 => Some(Self::Flex),
+            _ => None,
+        }
+    }
+
+    /// Detect the model from a GATT service UUID advertised by a bluetooth device
+    pub fn from_ble_service(service: &Uuid) -> Option<Self> {
+        BLE_SERVICES
+            .iter()
+            .find(|(uuid, _)| uuid == service)
+            .map(|(_, model)| *model)
+    }
+
+    /// Detect the model from the advertised name of a bluetooth device, e.g. `Nano X 1A2B`.
+    /// Only used when a device does not advertise its service.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::NanoX, Self::Stax, Self::Flex]
+            .into_iter()
+            .find(|model| name.starts_with(&model.to_string()))
+    }
+
+    /// The largest payload a single APDU sent to this model can carry
+    pub fn max_apdu_payload(self) -> usize {
+        MAX_APDU_PAYLOAD
+    }
+
+    /// The ATT MTU this model negotiates over bluetooth, `None` for models without bluetooth.
+    ///
+    /// The transport queries the MTU of each connection itself and does not report
+    /// it, so this is the value the firmware settles on with current hosts.
+    pub fn ble_mtu(self) -> Option<usize> {
+        match self {
+            Self::NanoS | Self::NanoSPlus => None,
+            Self::NanoX | Self::Stax | Self::Flex => Some(156),
+        }
+    }
+}
+
+impl Display for LedgerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NanoS => write!(f, "Nano S"),
+            Self::NanoSPlus => write!(f, "Nano S Plus"),
+            Self::NanoX => write!(f, "Nano X"),
+            Self::Stax => write!(f, "Stax"),
+            Self::Flex => write!(f, "Flex"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_usb_product_id() {
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x0001),
+            Some(LedgerModel::NanoS)
+        );
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x1011),
+            Some(LedgerModel::NanoS)
+        );
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x4015),
+            Some(LedgerModel::NanoX)
+        );
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x5011),
+            Some(LedgerModel::NanoSPlus)
+        );
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x6011),
+            Some(LedgerModel::Stax)
+        );
+        assert_eq!(
+            LedgerModel::from_usb_product_id(0x7011),
+            Some(LedgerModel::Flex)
+        );
+        assert_eq!(LedgerModel::from_usb_product_id(0x2011), None);
+    }
+
+    #[test]
+    fn test_from_ble_service() {
+        let stax = Uuid::parse_str("13d63400-2c97-6004-0000-4c6564676572").unwrap();
+        assert_eq!(
+            LedgerModel::from_ble_service(&stax),
+            Some(LedgerModel::Stax)
+        );
+        // the write characteristic of the same service is not a service
+        let write = Uuid::parse_str("13d63400-2c97-6004-0002-4c6564676572").unwrap();
+        assert_eq!(LedgerModel::from_ble_service(&write), None);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            LedgerModel::from_name("Nano X 1A2B"),
+            Some(LedgerModel::NanoX)
+        );
+        assert_eq!(LedgerModel::from_name("Stax 9F00"), Some(LedgerModel::Stax));
+        assert_eq!(LedgerModel::from_name("Keyboard"), None);
+    }
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(LedgerModel::NanoS.max_apdu_payload(), 255);
+        assert_eq!(LedgerModel::NanoS.ble_mtu(), None);
+        assert_eq!(LedgerModel::Flex.ble_mtu(), Some(156));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{apdu::HARDENED, error::LedgerUtilityError};
+
+/// The deepest path Ledger apps derive keys for
+pub const MAX_DEPTH: usize = 10;
+
+/// How the indices of a [DerivationPath] are laid out in a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathEncoding {
+    /// The number of indices as one byte, then each index big-endian, as
+    /// most apps expect
+    #[default]
+    BigEndian,
+    /// Each index little-endian without a count, as the Cosmos app expects
+    LittleEndian,
+}
+
+/// A BIP32 derivation path such as `m/44'/118'/0'/0/0`.
+///
+/// Hardened indices are written with a trailing `'`, `h` or `H` and stored with
+/// [HARDENED] added.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", try_from = "String")
+)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// The path from the master key, as given by raw indices
+    pub fn new(indices: Vec<u32>) -> Result<Self, LedgerUtilityError> {
+        if indices.len() > MAX_DEPTH {
+            return Err(LedgerUtilityError::InvalidDerivationPath(format!(
+                "{} levels, at most {} are supported",
+                indices.len(),
+                MAX_DEPTH
+            )));
+        }
+        Ok(Self(indices))
+    }
+
+    /// `m/44'/<coin_type>'/<account>'/<change>/<address_index>`
+    pub fn bip44(
+        coin_type: u32,
+        account: u32,
+        change: u32,
+        address_index: u32,
+    ) -> Result<Self, LedgerUtilityError> {
+        let hardened = |index| match index < HARDENED {
+            true => Ok(index | HARDENED),
+            false => Err(out_of_range(index)),
+        };
+        let path = Self(vec![
+            hardened(44)?,
+            hardened(coin_type)?,
+            hardened(account)?,
+            change,
+            address_index,
+        ]);
+        match path.is_bip44() {
+            true => Ok(path),
+            false => Err(out_of_range(change.max(address_index))),
+        }
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether the path has the BIP44 layout: five levels of which the purpose
+    /// `44`, the coin type and the account are hardened and the others are not
+    pub fn is_bip44(&self) -> bool {
+        matches!(
+            self.0[..],
+            [purpose, coin_type, account, change, address_index]
+                if purpose == 44 | HARDENED
+                    && coin_type >= HARDENED
+                    && account >= HARDENED
+                    && change < HARDENED
+                    && address_index < HARDENED
+        )
+    }
+
+    /// Check that the first `levels` indices are hardened, as apps require
+    /// before they derive a key
+    pub fn require_hardened(&self, levels: usize) -> Result<(), LedgerUtilityError> {
+        match self
+            .0
+            .iter()
+            .take(levels)
+            .position(|&index| index < HARDENED)
+        {
+            None if self.0.len() >= levels => Ok(()),
+            None => Err(LedgerUtilityError::InvalidDerivationPath(format!(
+                "{} is shorter than {} hardened levels",
+                self, levels
+            ))),
+            Some(level) => Err(LedgerUtilityError::InvalidDerivationPath(format!(
+                "level {} of {} must be hardened",
+                level + 1,
+                self
+            ))),
+        }
+    }
+
+    /// The path as a command expects it
+    pub fn encode(&self, encoding: PathEncoding) -> Vec<u8> {
+        match encoding {
+            PathEncoding::BigEndian => std::iter::once(self.0.len() as u8)
+                .chain(self.0.iter().flat_map(|index| index.to_be_bytes()))
+                .collect(),
+            PathEncoding::LittleEndian => self
+                .0
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+fn out_of_range(index: u32) -> LedgerUtilityError {
+    LedgerUtilityError::InvalidDerivationPath(format!("index {} is not below 2^31", index))
+}
+
+/// Formats as `m/44'/118'/0'/0/0`
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m")?;
+        for &index in &self.0 {
+            match index >= HARDENED {
+                true => write!(f, "/{}'", index - HARDENED)?,
+                false => write!(f, "/{}", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = LedgerUtilityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| {
+            LedgerUtilityError::InvalidDerivationPath(format!("{}: {}", s, reason))
+        };
+        let mut levels = s.split('/');
+        if levels.next() != Some("m") {
+            return Err(invalid(String::from("must start with m")));
+        }
+        let indices = levels
+            .map(|level| {
+                let (number, hardened) = match level.strip_suffix(['\'', 'h', 'H']) {
+                    Some(number) => (number, true),
+                    None => (level, false),
+                };
+                let index: u32 = number
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid level {:?}", level)))?;
+                match (index < HARDENED, hardened) {
+                    (true, true) => Ok(index | HARDENED),
+                    (true, false) => Ok(index),
+                    (false, _) => Err(invalid(format!("index {} is not below 2^31", index))),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Self::new(indices)
+    }
+}
+
+impl TryFrom<Vec<u32>> for DerivationPath {
+    type Error = LedgerUtilityError;
+
+    fn try_from(indices: Vec<u32>) -> Result<Self, Self::Error> {
+        Self::new(indices)
+    }
+}
+
+impl From<DerivationPath> for String {
+    fn from(path: DerivationPath) -> Self {
+        path.to_string()
+    }
+}
+
+impl TryFrom<String> for DerivationPath {
+    type Error = LedgerUtilityError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        path.parse()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_derivation_path() {
+        let path: DerivationPath = "m/44'/118'/0'/0/0".parse().unwrap();
+        assert_eq!(path, DerivationPath::bip44(118, 0, 0, 0).unwrap());
+        assert_eq!(
+            path.indices(),
+            [44 | HARDENED, 118 | HARDENED, HARDENED, 0, 0]
+        );
+        assert!(path.is_bip44());
+        path.require_hardened(3).unwrap();
+        assert!(path.require_hardened(4).is_err());
+        assert_eq!(path.to_string(), "m/44'/118'/0'/0/0");
+        assert_eq!(
+            "m/44h/118H/0'".parse::<DerivationPath>().unwrap().depth(),
+            3
+        );
+        assert_eq!("m".parse::<DerivationPath>().unwrap().depth(), 0);
+
+        for invalid in [
+            "44'/0'",
+            "m/",
+            "m/x",
+            "m/2147483648",
+            "m/2147483648'",
+            "m/0/0/0/0/0/0/0/0/0/0/0",
+        ] {
+            assert!(invalid.parse::<DerivationPath>().is_err(), "{}", invalid);
+        }
+        assert!(DerivationPath::bip44(HARDENED, 0, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_encode_derivation_path() {
+        let path: DerivationPath = "m/44'/1'/2".parse().unwrap();
+        assert_eq!(
+            path.encode(PathEncoding::BigEndian),
+            [3, 0x80, 0, 0, 44, 0x80, 0, 0, 1, 0, 0, 0, 2]
+        );
+        assert_eq!(
+            path.encode(PathEncoding::LittleEndian),
+            [44, 0, 0, 0x80, 1, 0, 0, 0x80, 2, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Recording of APDU sessions and deterministic playback of recordings.
+//!
+//! A recording is a text file with one exchange per line:
+//!
+//! ```text
+//! # ledger-utility recording v1
+//! <offset ms> <duration ms> <command hex> <answer hex>
+//! <offset ms> <duration ms> <command hex> !<error>
+//! ```
+//!
+//! `offset` is the time since the start of the recording at which the command
+//! was sent, `duration` how long the device took to answer. Answers include
+//! the status word. Exchanges that failed, such as on a transport error, are
+//! recorded with the error message instead of an answer and replayed as
+//! [LedgerUtilityError::RecordedError]. Blank lines and lines starting with `#`
+//! are ignored.
+
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    ops::Deref,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{
+    error::LedgerUtilityError,
+    session::{SessionGuard, SessionLock},
+    Ledger,
+};
+
+const HEADER: &str = "# ledger-utility recording v1";
+
+/// Wraps a [Ledger] and logs every exchange to a recording
+pub struct Recorder {
+    inner: Box<Ledger>,
+    started: Instant,
+    log: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+    /// Record the exchanges of `inner` to a new file at `path`
+    pub fn create(inner: Ledger, path: impl AsRef<Path>) -> Result<Self, LedgerUtilityError> {
+        Self::new(inner, File::create(path)?)
+    }
+
+    /// Record the exchanges of `inner` to `log`
+    pub fn new(
+        inner: Ledger,
+        mut log: impl Write + Send + 'static,
+    ) -> Result<Self, LedgerUtilityError> {
+        writeln!(log, "{}", HEADER)?;
+        log.flush()?;
+        Ok(Self {
+            inner: Box::new(inner),
+            started: Instant::now(),
+            log: Mutex::new(Box::new(log)),
+        })
+    }
+
+    /// The wrapped ledger
+    pub fn inner(&self) -> &Ledger {
+        &self.inner
+    }
+
+    /// Stop recording and return the wrapped ledger
+    pub fn into_inner(self) -> Ledger {
+        *self.inner
+    }
+
+    pub async fn exchange<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.record(command, self.inner.exchange_transport(command))
+            .await
+    }
+
+    /// Like [Recorder::exchange], but a blocking wrapped ledger exchanges on a
+    /// separate thread, see [Ledger::exchange_detached]. An exchange abandoned
+    /// before it is answered is not recorded.
+    pub(crate) async fn exchange_detached<I>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.record(command, self.inner.exchange_detached(command, guard))
+            .await
+    }
+
+    /// Run `exchange` of `command` with the wrapped ledger and log its outcome
+    async fn record<I>(
+        &self,
+        command: &APDUCommand<I>,
+        exchange: impl Future<Output = Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]>,
+    {
+        let sent = Instant::now();
+        let result = exchange.await;
+        let duration = sent.elapsed();
+
+        let outcome = match &result {
+            Ok(answer) => {
+                let mut raw_answer = answer.apdu_data().to_vec();
+                raw_answer.extend_from_slice(&answer.retcode().to_be_bytes());
+                hex::encode(raw_answer)
+            }
+            Err(error) => format!("!{}", error.to_string().replace('\n', " ")),
+        };
+
+        let mut log = self.log.lock().expect("recording log poisoned");
+        writeln!(
+            log,
+            "{} {} {} {}",
+            sent.duration_since(self.started).as_millis(),
+            duration.as_millis(),
+            hex::encode(command.serialize()),
+            outcome
+        )?;
+        // flush every line so a crashed session still leaves a usable recording
+        log.flush()?;
+        result
+    }
+}
+
+/// A single exchange read back from a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedExchange {
+    pub offset: Duration,
+    pub duration: Duration,
+    pub command: Vec<u8>,
+    pub answer: Vec<u8>,
+    /// The error the exchange failed with, in which case `answer` is empty
+    pub error: Option<String>,
+}
+
+/// Serves the answers of a recording back in order.
+///
+/// Every command must match the recorded command of the current step byte for
+/// byte, otherwise the exchange fails with [LedgerUtilityError::ReplayDiverged].
+pub struct ReplayTransport {
+    steps: Vec<RecordedExchange>,
+    position: Mutex<usize>,
+    pub(crate) session: SessionLock,
+}
+
+impl ReplayTransport {
+    /// Load a recording from the file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LedgerUtilityError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load a recording from `reader`
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, LedgerUtilityError> {
+        let mut steps = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            steps.push(parse_line(line).map_err(|reason| {
+                LedgerUtilityError::InvalidRecording {
+                    line: index + 1,
+                    reason,
+                }
+            })?);
+        }
+        Ok(Self {
+            steps,
+            position: Mutex::new(0),
+            session: SessionLock::default(),
+        })
+    }
+
+    /// The exchanges of the recording
+    pub fn steps(&self) -> &[RecordedExchange] {
+        &self.steps
+    }
+
+    /// Check that every exchange of the recording has been replayed
+    pub fn verify(&self) -> Result<(), LedgerUtilityError> {
+        let position = *self.position.lock().expect("replay position poisoned");
+        match self.steps.len() - position {
+            0 => Ok(()),
+            remaining => Err(LedgerUtilityError::UnmetExpectations(remaining)),
+        }
+    }
+
+    pub fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let mut position = self.position.lock().expect("replay position poisoned");
+        let step = *position;
+        let command = command.serialize();
+
+        let recorded = self
+            .steps
+            .get(step)
+            .ok_or_else(|| LedgerUtilityError::ReplayDiverged {
+                step,
+                expected: String::from("end of recording"),
+                actual: hex::encode(&command),
+            })?;
+        if recorded.command != command {
+            return Err(LedgerUtilityError::ReplayDiverged {
+                step,
+                expected: hex::encode(&recorded.command),
+                actual: hex::encode(&command),
+            });
+        }
+
+        *position += 1;
+        if let Some(error) = &recorded.error {
+            return Err(LedgerUtilityError::RecordedError(error.clone()));
+        }
+        APDUAnswer::from_answer(recorded.answer.clone())
+            .map_err(|_| LedgerUtilityError::AnswerTooShort)
+    }
+}
+
+fn parse_line(line: &str) -> Result<RecordedExchange, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [offset, duration, command, answer, ..] = fields[..] else {
+        return Err(format!("expected 4 fields, found {}", fields.len()));
+    };
+    let millis = |field: &str| {
+        field
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|e| format!("invalid time `{}`: {}", field, e))
+    };
+    let bytes = |field: &str| hex::decode(field).map_err(|e| format!("invalid hex: {}", e));
+    // the message of a failed exchange runs to the end of the line
+    let (answer, error) = match answer.strip_prefix('!') {
+        Some(_) => (Vec::new(), Some(fields[3..].join(" ")[1..].to_string())),
+        None if fields.len() == 4 => (bytes(answer)?, None),
+        None => return Err(format!("expected 4 fields, found {}", fields.len())),
+    };
+    Ok(RecordedExchange {
+        offset: millis(offset)?,
+        duration: millis(duration)?,
+        command: bytes(command)?,
+        answer,
+        error,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use ledger_transport::Exchange;
+
+    use super::*;
+    use crate::test_util::{command, command_with};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records a session against a device that is itself replaying a hand-written recording
+    async fn record_session() -> Vec<u8> {
+        let device = "0 5 e001000000 0102039000\n10 2 e002000001aa 6985\n";
+        let device = ReplayTransport::from_reader(device.as_bytes()).unwrap();
+        let buffer = SharedBuffer::default();
+        let ledger =
+            Ledger::Recording(Recorder::new(Ledger::Replay(device), buffer.clone()).unwrap());
+
+        ledger.exchange(&command(0x01)).await.unwrap();
+        ledger
+            .exchange(&command_with(0x02, vec![0xaa]))
+            .await
+            .unwrap();
+
+        let recording = buffer.0.lock().unwrap().clone();
+        recording
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let recording = record_session().await;
+        let replay = ReplayTransport::from_reader(&recording[..]).unwrap();
+        assert_eq!(replay.steps().len(), 2);
+        assert_eq!(replay.steps()[0].command, vec![0xe0, 0x01, 0, 0, 0]);
+        assert_eq!(replay.steps()[0].answer, vec![1, 2, 3, 0x90, 0x00]);
+
+        let ledger = Ledger::Replay(replay);
+        let answer = ledger.exchange(&command(0x01)).await.unwrap();
+        assert_eq!(answer.data(), &[1, 2, 3]);
+        let answer = ledger
+            .exchange(&command_with(0x02, vec![0xaa]))
+            .await
+            .unwrap();
+        assert_eq!(answer.retcode(), 0x6985);
+
+        let Ledger::Replay(replay) = ledger else {
+            unreachable!()
+        };
+        replay.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_diverged() {
+        let recording = record_session().await;
+        let ledger = Ledger::Replay(ReplayTransport::from_reader(&recording[..]).unwrap());
+
+        ledger.exchange(&command(0x01)).await.unwrap();
+        let error = ledger
+            .exchange(&command_with(0x02, vec![0xbb]))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::ReplayDiverged { step: 1, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay_error() {
+        let device = ReplayTransport::from_reader("0 5 e001000000 9000\n".as_bytes()).unwrap();
+        let buffer = SharedBuffer::default();
+        let ledger =
+            Ledger::Recording(Recorder::new(Ledger::Replay(device), buffer.clone()).unwrap());
+        // the device diverges, standing in for a transport error
+        let error = ledger.exchange(&command(0x02)).await.unwrap_err();
+
+        let recording = buffer.0.lock().unwrap().clone();
+        let replay = ReplayTransport::from_reader(&recording[..]).unwrap();
+        assert_eq!(replay.steps()[0].error, Some(error.to_string()));
+        let ledger = Ledger::Replay(replay);
+        let replayed = ledger.exchange(&command(0x02)).await.unwrap_err();
+        assert!(
+            matches!(replayed, LedgerUtilityError::RecordedError(message) if message == error.to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_recording() {
+        let recording = format!("{}\n0 1 e001000000\n", HEADER);
+        let error = ReplayTransport::from_reader(recording.as_bytes())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::InvalidRecording { line: 2, .. }
+        ));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use ledger_transport::{async_trait, APDUAnswer, APDUCommand, Exchange};
+use tokio::sync::Mutex;
+
+use crate::{error::LedgerUtilityError, info::DeviceId, retry::RetryPolicy, Connection, Ledger};
+
+type Idempotent = Box<dyn Fn(&APDUCommand<&[u8]>) -> bool + Send + Sync>;
+
+/// A [Ledger] that reopens its device when the transport fails.
+///
+/// After a transport error (HID read failure, bluetooth disconnect, ...) the
+/// device is rediscovered through the [Connection] by its [DeviceId] and
+/// reopened according to the retry policy. Commands marked idempotent with
+/// [ResilientLedger::idempotent] are then sent again; any other command fails
+/// with the original error, since the device may already have acted on it.
+pub struct ResilientLedger {
+    connection: Arc<Connection>,
+    id: DeviceId,
+    retry_policy: RetryPolicy,
+    idempotent: Idempotent,
+    ledger: Mutex<Option<Arc<Ledger>>>,
+    reconnects: AtomicU32,
+}
+
+impl ResilientLedger {
+    /// Open the device `id`, retrying according to `retry_policy`
+    pub async fn open(
+        connection: Arc<Connection>,
+        id: DeviceId,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Self, LedgerUtilityError> {
+        let retry_policy = retry_policy.into();
+        let ledger = connection
+            .connect_with_id(&id, retry_policy.clone())
+            .await?;
+        Ok(Self::with_ledger(connection, id, ledger, retry_policy))
+    }
+
+    /// Wrap an open `ledger`. Fails with [LedgerUtilityError::MissingDeviceId]
+    /// if it was not opened from a [crate::Device].
+    pub fn new(
+        connection: Arc<Connection>,
+        ledger: Ledger,
+        retry_policy: impl Into<RetryPolicy>,
+    ) -> Result<Self, LedgerUtilityError> {
+        let id = ledger
+            .id()
+            .cloned()
+            .ok_or(LedgerUtilityError::MissingDeviceId)?;
+        Ok(Self::with_ledger(
+            connection,
+            id,
+            ledger,
+            retry_policy.into(),
+        ))
+    }
+
+    fn with_ledger(
+        connection: Arc<Connection>,
+        id: DeviceId,
+        ledger: Ledger,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            connection,
+            id,
+            retry_policy,
+            idempotent: Box::new(|_| false),
+            ledger: Mutex::new(Some(Arc::new(ledger))),
+            reconnects: AtomicU32::new(0),
+        }
+    }
+
+    /// Select the commands that are safe to send again after a reconnection,
+    /// such as reading a version or an address without displaying it
+    pub fn idempotent(
+        mut self,
+        predicate: impl Fn(&APDUCommand<&[u8]>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.idempotent = Box::new(predicate);
+        self
+    }
+
+    pub fn id(&self) -> &DeviceId {
+        &self.id
+    }
+
+    /// How many times the device has been reopened
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// The current ledger, reopening the device if the last one failed
+    pub async fn ledger(&self) -> Result<Arc<Ledger>, LedgerUtilityError> {
+        let mut current = self.ledger.lock().await;
+        if let Some(ledger) = &*current {
+            return Ok(ledger.clone());
+        }
+        let ledger = self
+            .connection
+            .connect_with_id(&self.id, self.retry_policy.clone())
+            .await?;
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            crate::stats::RECONNECTS,
+            "transport" => crate::stats::transport_label(self.id.transport())
+        )
+        .increment(1);
+        Ok(current.insert(Arc::new(ledger)).clone())
+    }
+
+    /// Drop `failed` so the next exchange reopens the device, unless another
+    /// caller already replaced it
+    async fn invalidate(&self, failed: &Arc<Ledger>) {
+        let mut current = self.ledger.lock().await;
+        if current
+            .as_ref()
+            .is_some_and(|ledger| Arc::ptr_eq(ledger, failed))
+        {
+            *current = None;
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for ResilientLedger {
+    type Error = LedgerUtilityError;
+    type AnswerType = Vec<u8>;
+
+    async fn exchange<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Self::AnswerType>, Self::Error>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        let idempotent = (self.idempotent)(&APDUCommand {
+            cla: command.cla,
+            ins: command.ins,
+            p1: command.p1,
+            p2: command.p2,
+            data: &command.data[..],
+        });
+        let mut attempts = 0;
+        loop {
+            let ledger = self.ledger().await?;
+            attempts += 1;
+            match ledger.exchange(command).await {
+                Err(e) if e.is_transport() => {
+                    self.invalidate(&ledger).await;
+                    if !idempotent || attempts >= self.retry_policy.max_attempts {
+                        return Err(e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tcp"))]
+mod test {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::test_util::{command, emulator};
+
+    #[tokio::test]
+    #[serial]
+    async fn test_reconnect() {
+        // the first connection drops every command, the later ones answer them
+        let mut dropped = false;
+        let address = emulator(move |_| match std::mem::replace(&mut dropped, true) {
+            true => Some(vec![0x90, 0x00]),
+            false => None,
+        });
+
+        let connection = Connection::builder()
+            .tcp_endpoint(address)
+            .build()
+            .await
+            .unwrap();
+        let ledger = ResilientLedger::open(
+            Arc::new(connection),
+            DeviceId::Tcp(address),
+            RetryPolicy::new(3),
+        )
+        .await
+        .unwrap()
+        .idempotent(|command| command.ins == 0x01);
+
+        let answer = ledger.exchange(&command(0x01)).await.unwrap();
+        assert_eq!(answer.retcode(), 0x9000);
+        assert_eq!(ledger.reconnects(), 1);
+        ledger.exchange(&command(0x02)).await.unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use crate::error::LedgerUtilityError;
+
+/// How often and how patiently an operation such as discovery or connection is retried.
+///
+/// The delay between attempts starts at `initial_backoff` and is multiplied by
+/// `multiplier` after every failure, up to `max_backoff`. Once `deadline` has
+/// passed since the first attempt no further attempts are made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+            multiplier: 2,
+            deadline: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// `n` attempts with the default backoff
+impl From<u8> for RetryPolicy {
+    fn from(max_attempts: u8) -> Self {
+        Self::new(max_attempts.into())
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// A single attempt without any waiting
+    pub fn once() -> Self {
+        Self {
+            max_attempts: 1,
+            deadline: None,
+            ..Self::default()
+        }
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// The delay before the attempt following the `failures`th failure
+    pub fn delay(&self, failures: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Run `operation` until it succeeds or the policy is exhausted.
+    ///
+    /// Fails with [LedgerUtilityError::Timeout] carrying the error of the last attempt.
+    pub async fn retry<T, F, Fut>(&self, mut operation: F) -> Result<T, LedgerUtilityError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, LedgerUtilityError>>,
+    {
+        let started = Instant::now();
+        let mut last = None;
+        let mut attempts = 0;
+        while attempts < self.max_attempts {
+            if attempts > 0 {
+                let delay = self.delay(attempts);
+                if self.remaining(started).is_some_and(|left| left <= delay) {
+                    break;
+                }
+                tokio::time::sleep(delay).await;
+            }
+            attempts += 1;
+
+            let result = match self.remaining(started) {
+                Some(left) => match tokio::time::timeout(left, operation()).await {
+                    Ok(result) => result,
+                    Err(_) => break,
+                },
+                None => operation().await,
+            };
+            match result {
+                Ok(value) => return Ok(value),
+                Err(e) => last = Some(Box::new(e)),
+            }
+        }
+        Err(LedgerUtilityError::Timeout { attempts, last })
+    }
+
+    fn remaining(&self, started: Instant) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_sub(started.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn fast(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts).backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy =
+            RetryPolicy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let calls = Cell::new(0);
+        let result = fast(5)
+            .retry(|| async {
+                calls.set(calls.get() + 1);
+                match calls.get() {
+                    3 => Ok(calls.get()),
+                    _ => Err(LedgerUtilityError::DeviceNotFound),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_exhausted() {
+        let error = fast(3)
+            .retry(|| async { Err::<(), _>(LedgerUtilityError::DeviceNotFound) })
+            .await
+            .unwrap_err();
+        match error {
+            LedgerUtilityError::Timeout { attempts, last } => {
+                assert_eq!(attempts, 3);
+                assert!(matches!(
+                    last.as_deref(),
+                    Some(LedgerUtilityError::DeviceNotFound)
+                ));
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_deadline() {
+        let policy = fast(1000)
+            .backoff(Duration::from_millis(20), Duration::from_millis(20))
+            .deadline(Some(Duration::from_millis(50)));
+        let error = policy
+            .retry(|| async { Err::<(), _>(LedgerUtilityError::DeviceNotFound) })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::Timeout { attempts, .. } if attempts < 5
+        ));
+    }
+}
@@ -0,0 +1,487 @@
+//! APDU scripts: sequences of commands with expected status words, captures
+//! and assertions, for regression testing an app without writing Rust.
+//!
+//! A script has one statement per line. Blank lines and lines starting with
+//! `#` are ignored. Every `send` starts a new step; the statements after it
+//! apply to its answer.
+//!
+//! ```text
+//! # ledger-utility script v1
+//! send e0 01 00 00
+//! status 9000
+//! capture version 1..4
+//! send e0 02 00 00 ${version} 0102
+//! expect 0..2 aabb
+//! ```
+//!
+//! - `send <cla> <ins> <p1> <p2> [data...]` sends an APDU. The data is the
+//!   concatenation of the remaining hex tokens and `${name}` captures, and Lc
+//!   is computed from it. It must fit in a short APDU, see [MAX_DATA].
+//! - `status <sw>` sets the expected status word, `9000` by default.
+//! - `capture <name> <range>` saves bytes of the answer data as `${name}`.
+//! - `expect <range> <hex...>` asserts bytes of the answer data.
+//!
+//! Ranges are byte offsets into the answer data: `3`, `1..4` or `4..`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use ledger_transport::{APDUCommand, Exchange};
+
+use crate::{apdu::MAX_DATA, error::LedgerUtilityError, status::ApduStatus, Ledger};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Bytes(Vec<u8>),
+    Capture(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Range {
+    start: usize,
+    end: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Check {
+    Capture { name: String, range: Range },
+    Expect { range: Range, bytes: Vec<Segment> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    line: usize,
+    header: [u8; 4],
+    data: Vec<Segment>,
+    status: u16,
+    checks: Vec<Check>,
+}
+
+/// A parsed APDU script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Load a script from the file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LedgerUtilityError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// The number of APDUs the script sends
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl FromStr for Script {
+    type Err = LedgerUtilityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps: Vec<Step> = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| LedgerUtilityError::InvalidScript {
+                line: index + 1,
+                reason,
+            };
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().expect("line is not empty");
+            let args: Vec<&str> = tokens.collect();
+
+            if keyword == "send" {
+                steps.push(parse_send(index + 1, &args).map_err(invalid)?);
+                continue;
+            }
+            let step = steps
+                .last_mut()
+                .ok_or_else(|| invalid(format!("`{}` before the first `send`", keyword)))?;
+            match (keyword, &args[..]) {
+                ("status", [status]) => {
+                    step.status = u16::from_str_radix(status, 16)
+                        .map_err(|e| invalid(format!("invalid status `{}`: {}", status, e)))?
+                }
+                ("capture", [name, range]) => step.checks.push(Check::Capture {
+                    name: name.to_string(),
+                    range: parse_range(range).map_err(invalid)?,
+                }),
+                ("expect", [range, bytes @ ..]) if !bytes.is_empty() => {
+                    step.checks.push(Check::Expect {
+                        range: parse_range(range).map_err(invalid)?,
+                        bytes: parse_segments(bytes).map_err(invalid)?,
+                    })
+                }
+                ("status" | "capture" | "expect", _) => {
+                    return Err(invalid(format!(
+                        "wrong number of arguments to `{}`",
+                        keyword
+                    )))
+                }
+                _ => return Err(invalid(format!("unknown statement `{}`", keyword))),
+            }
+        }
+        Ok(Self { steps })
+    }
+}
+
+fn parse_send(line: usize, args: &[&str]) -> Result<Step, String> {
+    if args.len() < 4 {
+        return Err(String::from("`send` needs at least CLA, INS, P1 and P2"));
+    }
+    let mut header = [0; 4];
+    for (byte, arg) in header.iter_mut().zip(args) {
+        *byte =
+            u8::from_str_radix(arg, 16).map_err(|e| format!("invalid byte `{}`: {}", arg, e))?;
+    }
+    let data = parse_segments(&args[4..])?;
+    // captures can only add to the literal bytes
+    let literal = data
+        .iter()
+        .map(|segment| match segment {
+            Segment::Bytes(bytes) => bytes.len(),
+            Segment::Capture(_) => 0,
+        })
+        .sum();
+    check_length(literal)?;
+    Ok(Step {
+        line,
+        header,
+        data,
+        status: ApduStatus::Success.code(),
+        checks: Vec::new(),
+    })
+}
+
+fn check_length(len: usize) -> Result<(), String> {
+    match len <= MAX_DATA {
+        true => Ok(()),
+        false => Err(format!(
+            "{} bytes of data, at most {} fit in an APDU",
+            len, MAX_DATA
+        )),
+    }
+}
+
+fn parse_segments(tokens: &[&str]) -> Result<Vec<Segment>, String> {
+    tokens
+        .iter()
+        .map(|token| match token.strip_prefix("${") {
+            Some(name) => name
+                .strip_suffix('}')
+                .map(|name| Segment::Capture(name.to_string()))
+                .ok_or_else(|| format!("unterminated capture `{}`", token)),
+            None => hex::decode(token)
+                .map(Segment::Bytes)
+                .map_err(|e| format!("invalid hex `{}`: {}", token, e)),
+        })
+        .collect()
+}
+
+fn parse_range(range: &str) -> Result<Range, String> {
+    let offset = |x: &str| {
+        x.parse::<usize>()
+            .map_err(|e| format!("invalid range `{}`: {}", range, e))
+    };
+    match range.split_once("..") {
+        Some((start, "")) => Ok(Range {
+            start: offset(start)?,
+            end: None,
+        }),
+        Some((start, end)) => Ok(Range {
+            start: offset(start)?,
+            end: Some(offset(end)?),
+        }),
+        None => {
+            let start = offset(range)?;
+            let end = start
+                .checked_add(1)
+                .ok_or_else(|| format!("invalid range `{}`: offset too large", range))?;
+            Ok(Range {
+                start,
+                end: Some(end),
+            })
+        }
+    }
+}
+
+/// The outcome of a single step of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepReport {
+    /// The line of the `send` statement
+    pub line: usize,
+    pub command: Vec<u8>,
+    pub status: u16,
+    pub data: Vec<u8>,
+    pub duration: Duration,
+    /// Why the step failed, `None` if it passed
+    pub failure: Option<String>,
+}
+
+impl StepReport {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// The steps run by [run_script]. A script stops at its first failed step, so
+/// only the last step can have failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl ScriptReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(StepReport::passed)
+    }
+
+    /// Turn a failed step into [LedgerUtilityError::ScriptFailed]
+    pub fn result(&self) -> Result<(), LedgerUtilityError> {
+        match self.steps.iter().find(|step| !step.passed()) {
+            Some(step) => Err(LedgerUtilityError::ScriptFailed {
+                line: step.line,
+                reason: step.failure.clone().unwrap_or_default(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run `script` against `ledger`, stopping at the first failed step.
+///
+/// Failed assertions are reported in the [ScriptReport]; only transport
+/// errors are returned as errors.
+pub async fn run_script(
+    ledger: &Ledger,
+    script: &Script,
+) -> Result<ScriptReport, LedgerUtilityError> {
+    let mut captures = HashMap::new();
+    let mut steps = Vec::new();
+    for step in &script.steps {
+        let data =
+            resolve(&step.data, &captures).and_then(|data| check_length(data.len()).map(|()| data));
+        let (data, mut failure) = match data {
+            Ok(data) => (data, None),
+            Err(reason) => (Vec::new(), Some(reason)),
+        };
+        let command = APDUCommand {
+            cla: step.header[0],
+            ins: step.header[1],
+            p1: step.header[2],
+            p2: step.header[3],
+            data,
+        };
+        let mut report = StepReport {
+            line: step.line,
+            command: command.serialize(),
+            status: 0,
+            data: Vec::new(),
+            duration: Duration::ZERO,
+            failure: None,
+        };
+
+        if failure.is_none() {
+            let started = Instant::now();
+            let answer = ledger.exchange(&command).await?;
+            report.duration = started.elapsed();
+            report.status = answer.retcode();
+            report.data = answer.data().to_vec();
+            failure = check(step, &report, &mut captures).err();
+        }
+
+        report.failure = failure;
+        let passed = report.passed();
+        steps.push(report);
+        if !passed {
+            break;
+        }
+    }
+    Ok(ScriptReport { steps })
+}
+
+fn resolve(segments: &[Segment], captures: &HashMap<String, Vec<u8>>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for segment in segments {
+        match segment {
+            Segment::Bytes(data) => bytes.extend_from_slice(data),
+            Segment::Capture(name) => bytes.extend_from_slice(
+                captures
+                    .get(name)
+                    .ok_or_else(|| format!("`{}` was never captured", name))?,
+            ),
+        }
+    }
+    Ok(bytes)
+}
+
+fn check(
+    step: &Step,
+    report: &StepReport,
+    captures: &mut HashMap<String, Vec<u8>>,
+) -> Result<(), String> {
+    if report.status != step.status {
+        return Err(format!(
+            "expected status {}, got {}",
+            ApduStatus::from(step.status),
+            ApduStatus::from(report.status)
+        ));
+    }
+    for check in &step.checks {
+        let slice = |range: &Range| {
+            let end = range.end.unwrap_or(report.data.len());
+            report.data.get(range.start..end).ok_or_else(|| {
+                format!(
+                    "range {}..{} is out of the {} byte(s) of the answer",
+                    range.start,
+                    end,
+                    report.data.len()
+                )
+            })
+        };
+        match check {
+            Check::Capture { name, range } => {
+                captures.insert(name.clone(), slice(range)?.to_vec());
+            }
+            Check::Expect { range, bytes } => {
+                let expected = resolve(bytes, captures)?;
+                let actual = slice(range)?;
+                if actual != expected {
+                    return Err(format!(
+                        "expected {} at {}.., got {}",
+                        hex::encode(expected),
+                        range.start,
+                        hex::encode(actual)
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let script: Script = "# test\nsend e0 01 00 00\nstatus 6985\n\nsend e0 02 00 00 aa ${x}\n"
+            .parse()
+            .unwrap();
+        assert_eq!(script.len(), 2);
+        assert_eq!(script.steps[0].status, 0x6985);
+        assert_eq!(
+            script.steps[1].data,
+            vec![
+                Segment::Bytes(vec![0xaa]),
+                Segment::Capture(String::from("x"))
+            ]
+        );
+
+        let error = "send e0 01 00 00\nexpect 1..x aa\n"
+            .parse::<Script>()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::InvalidScript { line: 2, .. }
+        ));
+        assert!("status 9000\n".parse::<Script>().is_err());
+        let overflow = format!("send e0 01 00 00\ncapture x {}\n", usize::MAX);
+        assert!(matches!(
+            overflow.parse::<Script>(),
+            Err(LedgerUtilityError::InvalidScript { line: 2, .. })
+        ));
+
+        let too_long = format!("send e0 01 00 00 {} {}\n", "00".repeat(255), "00");
+        assert!(matches!(
+            too_long.parse::<Script>(),
+            Err(LedgerUtilityError::InvalidScript { line: 1, .. })
+        ));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_run_script() {
+        use crate::mock::{Expectation, MockTransport};
+
+        let mock = MockTransport::new();
+        mock.expect(
+            Expectation::new()
+                .ins(0x01)
+                .respond(vec![1, 2, 3, 4], 0x9000),
+        )
+        .expect(
+            Expectation::new()
+                .ins(0x02)
+                .data(vec![2, 3, 0xff])
+                .respond(vec![0xaa, 0xbb], 0x9000),
+        )
+        .expect(Expectation::new().ins(0x03).respond(vec![], 0x6985));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let script: Script = "send e0 01 00 00
+            capture version 1..3
+            expect 3.. 04
+            send e0 02 00 00 ${version} ff
+            expect 0..2 aabb
+            send e0 03 00 00
+            send e0 04 00 00
+            "
+        .parse()
+        .unwrap();
+        let report = run_script(&ledger, &script).await.unwrap();
+
+        // the third step fails and the fourth is never sent
+        assert_eq!(report.steps.len(), 3);
+        assert!(report.steps[0].passed() && report.steps[1].passed());
+        assert_eq!(
+            report.steps[1].command,
+            vec![0xe0, 0x02, 0, 0, 3, 2, 3, 0xff]
+        );
+        assert!(!report.passed());
+        assert!(matches!(
+            report.result(),
+            Err(LedgerUtilityError::ScriptFailed { line: 6, .. })
+        ));
+        mock.verify().unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_captured_data_too_long() {
+        use crate::mock::{Expectation, MockTransport};
+
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().ins(0x01).respond(vec![7; 200], 0x9000));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let script: Script = "send e0 01 00 00
+            capture blob 0..
+            send e0 02 00 00 ${blob} ${blob}
+            "
+        .parse()
+        .unwrap();
+        let report = run_script(&ledger, &script).await.unwrap();
+
+        // the second command is never sent
+        assert_eq!(report.steps.len(), 2);
+        assert!(matches!(
+            report.result(),
+            Err(LedgerUtilityError::ScriptFailed { line: 3, .. })
+        ));
+        mock.verify().unwrap();
+    }
+}
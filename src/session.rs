@@ -0,0 +1,192 @@
+use std::{ops::Deref, sync::Arc, time::Duration};
+
+use ledger_transport::{async_trait, APDUAnswer, APDUCommand, Exchange};
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
+
+use crate::{
+    chunk::{self, ChunkMarkers},
+    dashboard::Dashboard,
+    error::LedgerUtilityError,
+    status, Ledger,
+};
+
+/// A [SessionLock] guard that does not borrow the ledger
+pub(crate) type SessionGuard = OwnedMutexGuard<()>;
+
+/// Serializes the exchanges of a ledger. Every transport owns one.
+#[derive(Debug, Default)]
+pub(crate) struct SessionLock(Arc<Mutex<()>>);
+
+impl SessionLock {
+    pub(crate) async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.0.lock().await
+    }
+
+    /// Lock for an exchange that may outlive its caller, see
+    /// [Ledger::exchange_detached]
+    pub(crate) async fn lock_owned(&self) -> SessionGuard {
+        self.0.clone().lock_owned().await
+    }
+}
+
+/// Exclusive use of a [Ledger] for a sequence of exchanges.
+///
+/// While a session is alive every other exchange with the ledger, through
+/// [Exchange] or another session, waits for it to be dropped. Waiting callers
+/// are served in the order they started waiting. The session of a ledger
+/// shared by a [crate::daemon::Daemon] also holds off the other clients of
+/// the daemon.
+pub struct Session<'a> {
+    ledger: &'a Ledger,
+    // dropped before the guard, so the daemon is asked to release the device
+    // before the next local session can start
+    #[cfg(all(feature = "daemon", unix))]
+    _remote: Option<crate::daemon::RemoteSession<'a>>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) async fn acquire(
+        ledger: &'a Ledger,
+        timeout: Option<Duration>,
+    ) -> Result<Session<'a>, LedgerUtilityError> {
+        #[cfg(all(feature = "daemon", unix))]
+        let started = std::time::Instant::now();
+        let lock = ledger.session_lock().lock();
+        let guard = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, lock)
+                .await
+                .map_err(|_| LedgerUtilityError::SessionTimeout(timeout))?,
+            None => lock.await,
+        };
+        Ok(Self {
+            ledger,
+            // the daemon waits for whatever is left of the timeout
+            #[cfg(all(feature = "daemon", unix))]
+            _remote: ledger
+                .lock_remote(timeout.map(|timeout| timeout.saturating_sub(started.elapsed())))
+                .await?,
+            _guard: guard,
+        })
+    }
+
+    /// Dashboard commands sent within this session
+    pub fn dashboard(&self) -> Dashboard<'_> {
+        Dashboard::unlocked(self.ledger)
+    }
+
+    /// See [Ledger::exchange_checked]
+    pub async fn exchange_checked<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        status::check(self.exchange(command).await?)
+    }
+
+    /// See [Ledger::exchange_chunked]
+    pub async fn exchange_chunked<I>(
+        &self,
+        command: &APDUCommand<I>,
+        markers: ChunkMarkers,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        let chunks = chunk::split(&command.data, self.ledger.max_apdu_payload());
+        let count = chunks.len();
+        let mut answer = None;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let chunk_command = APDUCommand {
+                cla: command.cla,
+                ins: command.ins,
+                p1: markers.p1(index, count),
+                p2: command.p2,
+                data: chunk,
+            };
+            answer = Some(self.exchange_checked(&chunk_command).await?);
+        }
+        Ok(answer.expect("split returns at least one chunk"))
+    }
+}
+
+#[async_trait]
+impl Exchange for Session<'_> {
+    type Error = LedgerUtilityError;
+    type AnswerType = Vec<u8>;
+
+    async fn exchange<I>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Self::AnswerType>, Self::Error>
+    where
+        I: Deref<Target = [u8]> + Send + Sync,
+    {
+        self.ledger.exchange_unlocked(command).await
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        mock::{Expectation, MockTransport},
+        test_util::command,
+    };
+
+    #[tokio::test]
+    async fn test_session_is_exclusive() {
+        let mock = MockTransport::new();
+        for ins in [1, 2, 3, 4] {
+            mock.expect(Expectation::new().ins(ins));
+        }
+        let ledger = Ledger::Mock(mock.clone());
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let session = ledger.session().await.unwrap();
+        // queued behind the session, so it must not slip in between its exchanges
+        let other = async {
+            ledger.exchange(&command(4)).await.unwrap();
+            order.lock().unwrap().push(4);
+        };
+        let flow = async {
+            for ins in [1, 2, 3] {
+                session.exchange(&command(ins)).await.unwrap();
+                order.lock().unwrap().push(ins);
+                tokio::task::yield_now().await;
+            }
+            drop(session);
+        };
+        tokio::join!(other, flow);
+
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3, 4]);
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_dashboard() {
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().cla(0xb0).ins(0xa7));
+        let ledger = Ledger::Mock(mock.clone());
+
+        let session = ledger.session().await.unwrap();
+        session.dashboard().quit_app().await.unwrap();
+        mock.verify().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_timeout() {
+        let ledger = Ledger::Mock(MockTransport::new());
+        let _session = ledger.session().await.unwrap();
+        let error = ledger
+            .session_with_timeout(Duration::from_millis(10))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, LedgerUtilityError::SessionTimeout(_)));
+    }
+}
@@ -0,0 +1,167 @@
+//! Counters and histograms of exchanges and connections, recorded with the
+//! `metrics` crate.
+//!
+//! Install any `metrics` recorder, such as the Prometheus exporter, to collect
+//! them, and call [describe] once to register their units and descriptions.
+//! Transports are labelled with short lowercase names like `usb` or `bluetooth`.
+
+use std::{future::Future, ops::Deref, time::Instant};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+
+use crate::{error::LedgerUtilityError, Ledger, Transport};
+
+/// Exchanges, labelled by `transport`, `ins` and `sw`, the status word in hex
+/// or `error` if the exchange failed
+pub const EXCHANGES: &str = "ledger_exchanges_total";
+/// Exchange latency in seconds, labelled by `transport` and `ins`
+pub const EXCHANGE_DURATION: &str = "ledger_exchange_duration_seconds";
+/// Attempts to open a device, labelled by `transport`
+pub const CONNECT_ATTEMPTS: &str = "ledger_connect_attempts_total";
+/// Attempts to open a device that failed, labelled by `transport`
+pub const CONNECT_FAILURES: &str = "ledger_connect_failures_total";
+/// Devices reopened by a [crate::resilient::ResilientLedger], labelled by `transport`
+pub const RECONNECTS: &str = "ledger_reconnects_total";
+
+/// Register the units and descriptions of this crate's metrics with the installed recorder
+pub fn describe() {
+    describe_counter!(
+        EXCHANGES,
+        Unit::Count,
+        "APDU exchanges by instruction and status word"
+    );
+    describe_histogram!(EXCHANGE_DURATION, Unit::Seconds, "APDU exchange latency");
+    describe_counter!(CONNECT_ATTEMPTS, Unit::Count, "Attempts to open a device");
+    describe_counter!(
+        CONNECT_FAILURES,
+        Unit::Count,
+        "Failed attempts to open a device"
+    );
+    describe_counter!(
+        RECONNECTS,
+        Unit::Count,
+        "Devices reopened after a transport error"
+    );
+}
+
+pub(crate) fn transport_label(transport: Transport) -> String {
+    transport.to_string().to_lowercase()
+}
+
+/// Run `exchange` of `command` with `ledger`, counting it and recording its latency
+pub(crate) fn instrument<'a, I>(
+    ledger: &Ledger,
+    command: &APDUCommand<I>,
+    exchange: impl Future<Output = Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>> + 'a,
+) -> impl Future<Output = Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>> + 'a
+where
+    I: Deref<Target = [u8]>,
+{
+    let transport = ledger.kind();
+    let ins = format!("{:02x}", command.ins);
+    async move {
+        let start = Instant::now();
+        let result = exchange.await;
+        histogram!(EXCHANGE_DURATION, "transport" => transport, "ins" => ins.clone())
+            .record(start.elapsed());
+        let sw = match &result {
+            Ok(answer) => format!("{:04x}", answer.retcode()),
+            Err(_) => String::from("error"),
+        };
+        counter!(EXCHANGES, "transport" => transport, "ins" => ins, "sw" => sw).increment(1);
+        result
+    }
+}
+
+/// Count the attempt to open a device over `transport` and whether it failed
+pub(crate) async fn connect<T>(
+    transport: Transport,
+    connect: impl Future<Output = Result<T, LedgerUtilityError>>,
+) -> Result<T, LedgerUtilityError> {
+    let transport = transport_label(transport);
+    counter!(CONNECT_ATTEMPTS, "transport" => transport.clone()).increment(1);
+    let result = connect.await;
+    if result.is_err() {
+        counter!(CONNECT_FAILURES, "transport" => transport).increment(1);
+    }
+    result
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use futures::executor::block_on;
+    use ledger_transport::Exchange;
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        MetricKind,
+    };
+
+    use super::*;
+    use crate::{
+        mock::{Expectation, MockTransport},
+        test_util::command,
+    };
+
+    #[test]
+    fn test_exchange_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mock = MockTransport::new();
+            mock.expect(Expectation::new().respond(vec![], 0x9000))
+                .expect(Expectation::new().respond(vec![], 0x6985));
+            let ledger = Ledger::Mock(mock);
+            block_on(ledger.exchange(&command(0x01))).unwrap();
+            block_on(ledger.exchange(&command(0x01))).unwrap();
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |kind, name, label: (&str, &str)| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| {
+                    key.kind() == kind
+                        && key.key().name() == name
+                        && key.key().labels().any(|l| (l.key(), l.value()) == label)
+                })
+                .map(|(.., value)| value)
+        };
+        let counter = |sw| value(MetricKind::Counter, EXCHANGES, ("sw", sw));
+        assert_eq!(counter("9000"), Some(&DebugValue::Counter(1)));
+        assert_eq!(counter("6985"), Some(&DebugValue::Counter(1)));
+        let durations = value(MetricKind::Histogram, EXCHANGE_DURATION, ("ins", "01"));
+        assert!(matches!(durations, Some(DebugValue::Histogram(values)) if values.len() == 2));
+    }
+
+    #[cfg(feature = "replay")]
+    #[test]
+    fn test_recording_counted_once() {
+        use crate::replay::Recorder;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mock = MockTransport::new();
+            mock.expect(Expectation::new().respond(vec![], 0x9000));
+            let ledger =
+                Ledger::Recording(Recorder::new(Ledger::Mock(mock), std::io::sink()).unwrap());
+            block_on(ledger.exchange(&command(0x01))).unwrap();
+        });
+
+        // only the recording is counted, not the ledger it wraps
+        let exchanges: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, ..)| key.key().name() == EXCHANGES)
+            .collect();
+        assert_eq!(exchanges.len(), 1);
+        let (key, .., value) = &exchanges[0];
+        assert!(key
+            .key()
+            .labels()
+            .any(|l| (l.key(), l.value()) == ("transport", "recording")));
+        assert_eq!(value, &DebugValue::Counter(1));
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::Display;
+
+use ledger_transport::APDUAnswer;
+
+use crate::error::LedgerUtilityError;
+
+/// The status words returned by the Ledger firmware and apps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApduStatus {
+    /// 0x9000
+    Success,
+    /// 0x5515, the device is locked and must be unlocked with its PIN
+    DeviceLocked,
+    /// 0x6511, the requested app is not the one running, or no app is running
+    AppNotOpen,
+    /// 0x6700
+    WrongLength,
+    /// 0x6807, the app to open is not installed
+    AppNotInstalled,
+    /// 0x6982, the device is locked on older firmwares
+    SecurityStatusNotSatisfied,
+    /// 0x6985, the user rejected the request on the device
+    UserRejected,
+    /// 0x6A80
+    InvalidData,
+    /// 0x6A84
+    NotEnoughSpace,
+    /// 0x6B00
+    InvalidP1P2,
+    /// 0x6D00, the running app does not support the instruction
+    InsNotSupported,
+    /// 0x6E00, the running app does not support the class, usually the wrong app is open
+    ClaNotSupported,
+    /// 0x6F00
+    TechnicalProblem,
+    /// Any other status word
+    Unknown(u16),
+}
+
+impl ApduStatus {
+    pub fn code(self) -> u16 {
+        match self {
+            Self::Success => 0x9000,
+            Self::DeviceLocked => 0x5515,
+            Self::AppNotOpen => 0x6511,
+            Self::WrongLength => 0x6700,
+            Self::AppNotInstalled => 0x6807,
+            Self::SecurityStatusNotSatisfied => 0x6982,
+            Self::UserRejected => 0x6985,
+            Self::InvalidData => 0x6a80,
+            Self::NotEnoughSpace => 0x6a84,
+            Self::InvalidP1P2 => 0x6b00,
+            Self::InsNotSupported => 0x6d00,
+            Self::ClaNotSupported => 0x6e00,
+            Self::TechnicalProblem => 0x6f00,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    pub fn is_success(self) -> bool {
+        self == Self::Success
+    }
+}
+
+/// Turn an answer with a status word other than success into [LedgerUtilityError::Apdu]
+pub(crate) fn check(
+    answer: APDUAnswer<Vec<u8>>,
+) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+    match ApduStatus::from(answer.retcode()) {
+        ApduStatus::Success => Ok(answer),
+        status => Err(LedgerUtilityError::Apdu {
+            status,
+            data: answer.data().to_vec(),
+        }),
+    }
+}
+
+impl From<u16> for ApduStatus {
+    fn from(code: u16) -> Self {
+        match code {
+            0x9000 => Self::Success,
+            0x5515 => Self::DeviceLocked,
+            0x6511 => Self::AppNotOpen,
+            0x6700 => Self::WrongLength,
+            0x6807 => Self::AppNotInstalled,
+            0x6982 => Self::SecurityStatusNotSatisfied,
+            0x6985 => Self::UserRejected,
+            0x6a80 => Self::InvalidData,
+            0x6a84 => Self::NotEnoughSpace,
+            0x6b00 => Self::InvalidP1P2,
+            0x6d00 => Self::InsNotSupported,
+            0x6e00 => Self::ClaNotSupported,
+            0x6f00 => Self::TechnicalProblem,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<ApduStatus> for u16 {
+    fn from(status: ApduStatus) -> Self {
+        status.code()
+    }
+}
+
+impl Display for ApduStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::Success => "success",
+            Self::DeviceLocked => "device is locked",
+            Self::AppNotOpen => "app is not open",
+            Self::WrongLength => "wrong length",
+            Self::AppNotInstalled => "app is not installed",
+            Self::SecurityStatusNotSatisfied => "security status not satisfied",
+            Self::UserRejected => "rejected by the user",
+            Self::InvalidData => "invalid data",
+            Self::NotEnoughSpace => "not enough space",
+            Self::InvalidP1P2 => "invalid P1 or P2",
+            Self::InsNotSupported => "instruction not supported",
+            Self::ClaNotSupported => "class not supported, is the right app open?",
+            Self::TechnicalProblem => "technical problem",
+            Self::Unknown(_) => "unknown status",
+        };
+        write!(f, "{} (0x{:04x})", description, self.code())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for code in [0x9000, 0x5515, 0x6985, 0x6d00, 0x6e00, 0x6a80, 0x6f42] {
+            assert_eq!(ApduStatus::from(code).code(), code);
+        }
+        assert_eq!(ApduStatus::from(0x6985), ApduStatus::UserRejected);
+        assert_eq!(ApduStatus::from(0x6f42), ApduStatus::Unknown(0x6f42));
+        assert_eq!(
+            ApduStatus::UserRejected.to_string(),
+            "rejected by the user (0x6985)"
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_exchange_checked() {
+        use crate::{
+            error::LedgerUtilityError,
+            mock::{Expectation, MockTransport},
+            test_util::command,
+            Ledger,
+        };
+
+        let mock = MockTransport::new();
+        mock.expect(Expectation::new().respond(vec![1], 0x9000))
+            .expect(Expectation::new().respond(vec![2], 0x6985));
+        let ledger = Ledger::Mock(mock);
+        let command = command(0x02);
+
+        assert_eq!(
+            ledger.exchange_checked(&command).await.unwrap().data(),
+            &[1]
+        );
+        let error = ledger.exchange_checked(&command).await.unwrap_err();
+        assert!(matches!(
+            error,
+            LedgerUtilityError::Apdu {
+                status: ApduStatus::UserRejected,
+                data
+            } if data == vec![2]
+        ));
+    }
+}
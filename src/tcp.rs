@@ -0,0 +1,118 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+
+use crate::{
+    error::LedgerUtilityError,
+    info::{DeviceId, DeviceInfo},
+    session::{SessionGuard, SessionLock},
+};
+
+/// APDU transport for the Speculos and Zemu emulators.
+///
+/// Commands are sent as a big-endian `u32` length followed by the raw APDU.
+/// Answers come back as a big-endian `u32` length of the response data,
+/// followed by the data and the two byte status word.
+pub struct TcpTransport {
+    address: SocketAddr,
+    stream: Arc<Mutex<TcpStream>>,
+    info: DeviceInfo,
+    pub(crate) session: SessionLock,
+}
+
+impl TcpTransport {
+    /// Open a connection to an emulator listening on `address`
+    pub fn connect(address: SocketAddr) -> Result<Self, LedgerUtilityError> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            address,
+            stream: Arc::new(Mutex::new(stream)),
+            info: DeviceInfo::new(DeviceId::Tcp(address)),
+            session: SessionLock::default(),
+        })
+    }
+
+    /// The address of the emulator this transport is connected to
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    pub fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        exchange(&self.stream, &command.serialize())
+    }
+
+    /// Exchange on a blocking thread, so the caller can stop waiting for an
+    /// answer. An abandoned read keeps the stream busy until it is answered,
+    /// and holds `guard` until then.
+    pub(crate) async fn exchange_detached<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let stream = self.stream.clone();
+        let apdu = command.serialize();
+        tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            exchange(&stream, &apdu)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+fn exchange(
+    stream: &Mutex<TcpStream>,
+    apdu: &[u8],
+) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+    let mut stream = stream.lock().expect("TCP stream poisoned");
+
+    stream.write_all(&(apdu.len() as u32).to_be_bytes())?;
+    stream.write_all(apdu)?;
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    // the length prefix does not include the status word
+    let mut answer = vec![0u8; u32::from_be_bytes(len) as usize + 2];
+    stream.read_exact(&mut answer)?;
+
+    APDUAnswer::from_answer(answer).map_err(|_| LedgerUtilityError::AnswerTooShort)
+}
+
+#[cfg(test)]
+mod test {
+    use ledger_transport::Exchange;
+
+    use super::*;
+    use crate::{
+        test_util::{command_with, emulator},
+        Ledger,
+    };
+
+    #[tokio::test]
+    async fn test_exchange() {
+        // echo every APDU payload back
+        let address = emulator(|apdu| Some([&apdu[5..], &[0x90, 0x00]].concat()));
+        let ledger = Ledger::Tcp(TcpTransport::connect(address).unwrap());
+        for data in [vec![], vec![1, 2, 3]] {
+            let answer = ledger
+                .exchange(&command_with(0x01, data.clone()))
+                .await
+                .unwrap();
+            assert_eq!(answer.retcode(), 0x9000);
+            assert_eq!(answer.data(), &data[..]);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Fixtures shared by the unit tests
+
+// not every fixture is used under every set of features
+#![allow(dead_code)]
+
+use ledger_transport::APDUCommand;
+
+/// An app command without parameters or data
+pub(crate) fn command(ins: u8) -> APDUCommand<Vec<u8>> {
+    APDUCommand {
+        cla: 0xe0,
+        ins,
+        p1: 0,
+        p2: 0,
+        data: vec![],
+    }
+}
+
+/// An app command carrying `data`
+pub(crate) fn command_with(ins: u8, data: Vec<u8>) -> APDUCommand<Vec<u8>> {
+    APDUCommand {
+        data,
+        ..command(ins)
+    }
+}
+
+/// A connection that needs no device, for tests that do not enumerate
+#[cfg(feature = "mock")]
+pub(crate) async fn connection() -> crate::Connection {
+    let builder = crate::Connection::builder();
+    #[cfg(feature = "tcp")]
+    let builder = builder.tcp_endpoint("127.0.0.1:9999".parse().unwrap());
+    builder.build().await.unwrap()
+}
+
+/// The dashboard's answer to "get app and version" while `name` runs at `version`
+#[cfg(feature = "mock")]
+pub(crate) fn app(name: &str, version: &str) -> crate::mock::Expectation {
+    let mut answer = vec![1, name.len() as u8];
+    answer.extend_from_slice(name.as_bytes());
+    answer.push(version.len() as u8);
+    answer.extend_from_slice(version.as_bytes());
+    crate::mock::Expectation::new()
+        .cla(0xb0)
+        .ins(0x01)
+        .respond(answer, 0x9000)
+}
+
+/// Minimal stand-in for Speculos listening on a local port.
+///
+/// Every APDU received, on this or a later connection, is passed to `answer`,
+/// which returns the response data followed by the status word, or `None` to
+/// hang up on the client. It may block to keep the client waiting.
+#[cfg(feature = "tcp")]
+pub(crate) fn emulator(
+    mut answer: impl FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+) -> std::net::SocketAddr {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            loop {
+                let mut len = [0u8; 4];
+                if stream.read_exact(&mut len).is_err() {
+                    break;
+                }
+                let mut apdu = vec![0u8; u32::from_be_bytes(len) as usize];
+                if stream.read_exact(&mut apdu).is_err() {
+                    break;
+                }
+                let Some(response) = answer(&apdu) else {
+                    break;
+                };
+                let len = response.len() as u32 - 2;
+                if stream.write_all(&len.to_be_bytes()).is_err()
+                    || stream.write_all(&response).is_err()
+                {
+                    break;
+                }
+            }
+        }
+    });
+    address
+}
+
+/// Serve `daemon` on a fresh socket until `client`, given the socket's path, is done
+#[cfg(all(feature = "daemon", unix, feature = "mock"))]
+pub(crate) async fn with_daemon<F>(
+    daemon: &crate::daemon::Daemon,
+    client: impl FnOnce(std::path::PathBuf) -> F,
+) where
+    F: std::future::Future<Output = ()>,
+{
+    let path = std::env::temp_dir().join(format!("ledger-utility-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::select! {
+        result = daemon.serve(listener) => panic!("daemon stopped: {:?}", result.err()),
+        () = client(path.clone()) => {}
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,173 @@
+use std::{future::Future, time::Duration};
+
+use futures::future::{self, Either};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::LedgerUtilityError;
+
+/// Bounds on how long a single exchange may take, see [crate::Ledger::exchange_with_limits].
+///
+/// The transport timeout covers commands the device answers on its own. Commands
+/// that wait for the user to confirm on the device, such as signing, should use
+/// a confirmation timeout instead, which is reported as
+/// [LedgerUtilityError::ConfirmationTimeout]. An exchange has one or the other,
+/// whichever was set last.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeLimits {
+    deadline: Option<Deadline>,
+    cancel: Option<CancellationToken>,
+}
+
+/// How long the whole exchange may take, and what it means when that runs out
+#[derive(Debug, Clone, Copy)]
+enum Deadline {
+    Transport(Duration),
+    Confirmation(Duration),
+}
+
+impl ExchangeLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail with [LedgerUtilityError::ExchangeTimeout] if the answer takes longer than `timeout`,
+    /// replacing any confirmation timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Deadline::Transport(timeout));
+        self
+    }
+
+    /// The command waits for the user, fail with [LedgerUtilityError::ConfirmationTimeout]
+    /// if they have not acted within `timeout`, replacing any transport timeout
+    pub fn confirmation_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Deadline::Confirmation(timeout));
+        self
+    }
+
+    /// Fail with [LedgerUtilityError::Cancelled] as soon as `token` is cancelled
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub(crate) async fn run<T>(
+        &self,
+        operation: impl Future<Output = Result<T, LedgerUtilityError>>,
+    ) -> Result<T, LedgerUtilityError> {
+        let bounded = async {
+            let (limit, error): (_, fn(Duration) -> LedgerUtilityError) = match self.deadline {
+                Some(Deadline::Transport(limit)) => (limit, LedgerUtilityError::ExchangeTimeout),
+                Some(Deadline::Confirmation(limit)) => {
+                    (limit, LedgerUtilityError::ConfirmationTimeout)
+                }
+                None => return operation.await,
+            };
+            tokio::time::timeout(limit, operation)
+                .await
+                .unwrap_or_else(|_| Err(error(limit)))
+        };
+        let Some(token) = &self.cancel else {
+            return bounded.await;
+        };
+        if token.is_cancelled() {
+            return Err(LedgerUtilityError::Cancelled);
+        }
+        match future::select(Box::pin(token.cancelled()), Box::pin(bounded)).await {
+            Either::Left(_) => Err(LedgerUtilityError::Cancelled),
+            Either::Right((result, _)) => result,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tcp"))]
+mod test {
+    use std::thread;
+
+    use ledger_transport::Exchange;
+
+    use super::*;
+    use crate::{
+        tcp::TcpTransport,
+        test_util::{command, emulator},
+        Ledger,
+    };
+
+    /// An emulator that reads a command but does not answer, like a device waiting
+    /// for the user, then hangs up so the abandoned blocking read can finish
+    fn silent_server() -> Ledger {
+        let address = emulator(|_| {
+            thread::sleep(Duration::from_millis(200));
+            None
+        });
+        Ledger::Tcp(TcpTransport::connect(address).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let ledger = silent_server();
+        let error = ledger
+            .exchange_with_timeout(&command(0x04), Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::ExchangeTimeout(_)));
+
+        // a fresh emulator each time, the abandoned exchange keeps the last one locked
+        let ledger = silent_server();
+        let limits = ExchangeLimits::new()
+            .timeout(Duration::from_secs(60))
+            .confirmation_timeout(Duration::from_millis(20));
+        let error = ledger
+            .exchange_with_limits(&command(0x04), &limits)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::ConfirmationTimeout(_)));
+
+        let ledger = silent_server();
+        let limits = limits.timeout(Duration::from_millis(20));
+        let error = ledger
+            .exchange_with_limits(&command(0x04), &limits)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::ExchangeTimeout(_)));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_keeps_session() {
+        let ledger = silent_server();
+        ledger
+            .exchange_with_timeout(&command(0x04), Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        // the next exchange waits for the abandoned one without blocking the runtime
+        let command = command(0x04);
+        let next = tokio::time::timeout(Duration::from_millis(50), ledger.exchange(&command));
+        assert!(next.await.is_err());
+    }
+
+    #[cfg(feature = "replay")]
+    #[tokio::test]
+    async fn test_recording_timeout() {
+        use crate::replay::Recorder;
+
+        let ledger = Ledger::Recording(Recorder::new(silent_server(), std::io::sink()).unwrap());
+        let error = ledger
+            .exchange_with_timeout(&command(0x04), Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, LedgerUtilityError::ExchangeTimeout(_)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let ledger = silent_server();
+        let token = CancellationToken::new();
+        let limits = ExchangeLimits::new().cancel_on(token.clone());
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            token.cancel();
+        };
+        let command = command(0x04);
+        let (result, ()) = tokio::join!(ledger.exchange_with_limits(&command, &limits), cancel);
+        assert!(matches!(result, Err(LedgerUtilityError::Cancelled)));
+    }
+}
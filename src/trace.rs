@@ -0,0 +1,138 @@
+//! Spans and events for every APDU exchange, emitted with the `tracing` crate.
+//!
+//! Each exchange runs in an `apdu` span at debug level carrying the transport,
+//! the device id, the command header, the payload length and, once answered,
+//! the status word and the latency in microseconds. Failed exchanges emit a
+//! debug event with the error. Command and answer payloads are logged as trace
+//! events, subject to the process wide [Redaction] policy, since they may carry
+//! transactions and keys.
+
+use std::{fmt::Display, future::Future, ops::Deref, sync::RwLock, time::Instant};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use tracing::{field, Instrument};
+
+use crate::{error::LedgerUtilityError, Ledger};
+
+static REDACTION: RwLock<Redaction> = RwLock::new(Redaction::Hide);
+
+/// How much of the APDU payloads trace events reveal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Redaction {
+    /// Log only the length of payloads
+    #[default]
+    Hide,
+    /// Log the first bytes of payloads, enough to tell commands apart
+    Prefix(usize),
+    /// Log payloads in full
+    Reveal,
+}
+
+/// Set the redaction policy for payloads, [Redaction::Hide] until changed
+pub fn set_redaction(redaction: Redaction) {
+    *REDACTION.write().expect("redaction policy poisoned") = redaction;
+}
+
+pub fn redaction() -> Redaction {
+    *REDACTION.read().expect("redaction policy poisoned")
+}
+
+/// A payload formatted according to a [Redaction] policy
+struct Payload<'a> {
+    bytes: &'a [u8],
+    redaction: Redaction,
+}
+
+impl Display for Payload<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shown = match self.redaction {
+            Redaction::Hide => 0,
+            Redaction::Prefix(length) => length.min(self.bytes.len()),
+            Redaction::Reveal => self.bytes.len(),
+        };
+        write!(f, "{}", hex::encode(&self.bytes[..shown]))?;
+        match shown == self.bytes.len() {
+            true => Ok(()),
+            false if shown == 0 => write!(f, "<{} bytes redacted>", self.bytes.len()),
+            false => write!(f, "..<{} bytes redacted>", self.bytes.len() - shown),
+        }
+    }
+}
+
+/// Run `exchange` of `command` with `ledger` in an `apdu` span
+pub(crate) fn instrument<'a, I>(
+    ledger: &Ledger,
+    command: &'a APDUCommand<I>,
+    exchange: impl Future<Output = Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>> + 'a,
+) -> impl Future<Output = Result<APDUAnswer<Vec<u8>>, LedgerUtilityError>> + 'a
+where
+    I: Deref<Target = [u8]>,
+{
+    let span = tracing::debug_span!(
+        "apdu",
+        transport = ledger.kind(),
+        device = field::Empty,
+        cla = command.cla,
+        ins = command.ins,
+        p1 = command.p1,
+        p2 = command.p2,
+        length = command.data.len(),
+        sw = field::Empty,
+        latency_us = field::Empty,
+    );
+    if let Some(id) = ledger.id() {
+        span.record("device", field::display(id));
+    }
+    async move {
+        let redaction = redaction();
+        tracing::trace!(
+            data = %Payload {
+                bytes: &command.data,
+                redaction,
+            },
+            "command"
+        );
+        let start = Instant::now();
+        let result = exchange.await;
+        let span = tracing::Span::current();
+        span.record("latency_us", start.elapsed().as_micros() as u64);
+        match &result {
+            Ok(answer) => {
+                span.record(
+                    "sw",
+                    field::display(format_args!("{:04x}", answer.retcode())),
+                );
+                tracing::trace!(
+                    data = %Payload {
+                        bytes: answer.data(),
+                        redaction,
+                    },
+                    "answer"
+                );
+            }
+            Err(error) => tracing::debug!(%error, "exchange failed"),
+        }
+        result
+    }
+    .instrument(span)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_payload_redaction() {
+        let format = |redaction| {
+            Payload {
+                bytes: &[0xde, 0xad, 0xbe, 0xef],
+                redaction,
+            }
+            .to_string()
+        };
+        assert_eq!(format(Redaction::Hide), "<4 bytes redacted>");
+        assert_eq!(format(Redaction::Prefix(1)), "de..<3 bytes redacted>");
+        assert_eq!(format(Redaction::Prefix(8)), "deadbeef");
+        assert_eq!(format(Redaction::Reveal), "deadbeef");
+    }
+}
@@ -0,0 +1,88 @@
+use std::{ops::Deref, sync::Arc};
+
+use ledger_transport::{APDUAnswer, APDUCommand};
+use ledger_transport_hid::{
+    hidapi::{DeviceInfo as HidDeviceInfo, HidApi},
+    TransportNativeHID,
+};
+
+use crate::{
+    error::LedgerUtilityError,
+    info::{DeviceId, DeviceInfo},
+    model::LedgerModel,
+    session::{SessionGuard, SessionLock},
+};
+
+/// A device connected over USB HID
+pub struct UsbTransport {
+    transport: Arc<TransportNativeHID>,
+    info: DeviceInfo,
+    pub(crate) session: SessionLock,
+}
+
+impl UsbTransport {
+    /// Wrap a transport the caller opened, for the device described by `info`
+    pub fn new(transport: TransportNativeHID, info: DeviceInfo) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            info,
+            session: SessionLock::default(),
+        }
+    }
+
+    pub(crate) fn open(api: &HidApi, device: &HidDeviceInfo) -> Result<Self, LedgerUtilityError> {
+        let transport = TransportNativeHID::open_device(api, device)?;
+        Ok(Self::new(transport, describe(device)))
+    }
+
+    /// Metadata of the device this transport was opened from
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    pub fn exchange<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        Ok(self.transport.exchange(command)?)
+    }
+
+    /// Exchange on a blocking thread, so the caller can stop waiting for an
+    /// answer. An abandoned read keeps the device busy until it answers, and
+    /// holds `guard` until then.
+    pub(crate) async fn exchange_detached<I: Deref<Target = [u8]>>(
+        &self,
+        command: &APDUCommand<I>,
+        guard: Option<SessionGuard>,
+    ) -> Result<APDUAnswer<Vec<u8>>, LedgerUtilityError> {
+        let transport = self.transport.clone();
+        let command = APDUCommand {
+            cla: command.cla,
+            ins: command.ins,
+            p1: command.p1,
+            p2: command.p2,
+            data: command.data.to_vec(),
+        };
+        tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            Ok(transport.exchange(&command)?)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+pub(crate) fn describe(device: &HidDeviceInfo) -> DeviceInfo {
+    let mut info = DeviceInfo::new(id(device));
+    info.product = device.product_string().map(String::from);
+    info.vendor_id = Some(device.vendor_id());
+    info.product_id = Some(device.product_id());
+    info.serial_number = device.serial_number().map(String::from);
+    info.hid_path = Some(device.path().to_string_lossy().into_owned());
+    info.model = LedgerModel::from_usb_product_id(device.product_id());
+    info
+}
+
+pub(crate) fn id(device: &HidDeviceInfo) -> DeviceId {
+    DeviceId::Usb(device.path().to_string_lossy().into_owned())
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use futures::{stream, Stream, StreamExt};
+use ledger_transport::async_trait;
+
+use crate::{error::LedgerUtilityError, info::DeviceId, Connection, Device};
+
+/// How often [Connection::watch] enumerates devices
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change in the set of attached devices
+#[derive(Debug)]
+pub enum DeviceEvent<D = Device> {
+    Added(D),
+    Removed(DeviceId),
+}
+
+/// A source of the devices that are currently attached
+#[async_trait]
+pub trait Enumerator {
+    type Device: Send;
+
+    async fn enumerate(&self) -> Result<Vec<(DeviceId, Self::Device)>, LedgerUtilityError>;
+}
+
+#[async_trait]
+impl Enumerator for Connection {
+    type Device = Device;
+
+    async fn enumerate(&self) -> Result<Vec<(DeviceId, Device)>, LedgerUtilityError> {
+        Ok(self
+            .get_all_ledgers()
+            .await?
+            .into_iter()
+            .map(|device| (device.id(), device))
+            .collect())
+    }
+}
+
+/// Turns successive enumerations into [DeviceEvent]s
+#[derive(Debug, Default)]
+pub struct DeviceTracker {
+    known: HashSet<DeviceId>,
+}
+
+impl DeviceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the devices attached now and return what changed since the last update
+    pub fn update<D>(&mut self, current: Vec<(DeviceId, D)>) -> Vec<DeviceEvent<D>> {
+        let ids: HashSet<DeviceId> = current.iter().map(|(id, _)| id.clone()).collect();
+        let mut events: Vec<DeviceEvent<D>> = self
+            .known
+            .difference(&ids)
+            .cloned()
+            .map(DeviceEvent::Removed)
+            .collect();
+        events.extend(
+            current
+                .into_iter()
+                .filter(|(id, _)| !self.known.contains(id))
+                .map(|(_, device)| DeviceEvent::Added(device)),
+        );
+        self.known = ids;
+        events
+    }
+}
+
+/// Enumerate once immediately and then every time `wake` yields, reporting the
+/// changes as [DeviceEvent]s. Enumerations that fail are skipped.
+pub fn watch<'a, E>(
+    enumerator: &'a E,
+    wake: impl Stream<Item = ()> + Send + 'a,
+) -> impl Stream<Item = DeviceEvent<E::Device>> + 'a
+where
+    E: Enumerator + Sync,
+{
+    let state = (
+        DeviceTracker::new(),
+        VecDeque::new(),
+        stream::once(async {}).chain(wake).boxed(),
+    );
+    stream::unfold(
+        state,
+        move |(mut tracker, mut pending, mut wake)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((event, (tracker, pending, wake)));
+                }
+                wake.next().await?;
+                if let Ok(devices) = enumerator.enumerate().await {
+                    pending.extend(tracker.update(devices));
+                }
+            }
+        },
+    )
+}
+
+/// Yields every `interval`
+pub(crate) fn ticks(interval: Duration) -> impl Stream<Item = ()> + Send {
+    stream::unfold((), move |_| async move {
+        tokio::time::sleep(interval).await;
+        Some(((), ()))
+    })
+}
+
+#[cfg(all(test, feature = "usb"))]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn usb(path: &str) -> DeviceId {
+        DeviceId::Usb(String::from(path))
+    }
+
+    /// Replays a fixed list of enumerations, then fails
+    struct FakeEnumerator {
+        rounds: Mutex<VecDeque<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl Enumerator for FakeEnumerator {
+        type Device = &'static str;
+
+        async fn enumerate(&self) -> Result<Vec<(DeviceId, &'static str)>, LedgerUtilityError> {
+            let round = self
+                .rounds
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or(LedgerUtilityError::DeviceNotFound)?;
+            Ok(round.into_iter().map(|path| (usb(path), path)).collect())
+        }
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = DeviceTracker::new();
+        let events = tracker.update(vec![(usb("a"), "a"), (usb("b"), "b")]);
+        assert_eq!(events.len(), 2);
+        assert!(tracker
+            .update(vec![(usb("a"), "a"), (usb("b"), "b")])
+            .is_empty());
+
+        let events = tracker.update(vec![(usb("b"), "b"), (usb("c"), "c")]);
+        assert!(
+            matches!(&events[..], [DeviceEvent::Removed(id), DeviceEvent::Added("c")] if id == &usb("a"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let enumerator = FakeEnumerator {
+            rounds: Mutex::new(VecDeque::from(vec![
+                vec!["a"],
+                vec!["a"],
+                vec![],
+                vec!["b"],
+            ])),
+        };
+        let events: Vec<_> = watch(&enumerator, stream::repeat(()))
+            .take(3)
+            .collect()
+            .await;
+        assert!(matches!(
+            &events[..],
+            [
+                DeviceEvent::Added("a"),
+                DeviceEvent::Removed(removed),
+                DeviceEvent::Added("b")
+            ] if removed == &usb("a")
+        ));
+    }
+}